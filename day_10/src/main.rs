@@ -1,14 +1,15 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::collections::hash_map::HashMap;
 
-#[macro_use]
-extern crate lazy_static;
-
 extern crate regex;
 use regex::Regex;
 
-#[derive(Debug,Copy,Clone)]
+mod network;
+mod simulation;
+mod solver;
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
 enum Dest {
     None,
     Bot(i32),
@@ -41,6 +42,10 @@ impl Bot {
         }
     }
 
+    fn is_full(&self) -> bool {
+        self.chip_low >= 0 && self.chip_high >= 0
+    }
+
     fn add_chip(&mut self, id: i32) {
         if id > self.chip_high {
             self.chip_low = self.chip_high;
@@ -54,33 +59,33 @@ impl Bot {
 }
 
 fn require_bot(bots: &mut Vec<Bot>, bot_id: i32) {
-    if bot_id as usize > bots.len() {
+    if bot_id as usize >= bots.len() {
         bots.resize(bot_id as usize + 1, Bot::new());
     }
 }
 
-// Find the bot ID required to process [low_id, high_id]
-fn process(input: &str, low_id: i32, high_id: i32) -> (HashMap<i32, i32>, i32) {
-
-    let mut target_bot = -1i32;
-
-    let mut bots = Vec::new();
-    bots.reserve(500);
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+enum Instruction {
+    Value { chip: i32, bot: i32 },
+    Rule { bot: i32, low: Dest, high: Dest },
+}
 
+// Instructions in the input, with their line numbers counting from 1. Lines
+// that aren't instructions are skipped.
+fn parse_instructions(input: &str) -> Vec<(usize, Instruction)> {
     let regex_initial = Regex::new("value ([:digit:]+) goes to bot ([:digit:]+)").unwrap();
 
     let regex_bot = Regex::new("bot ([:digit:]+) gives low to ([:alpha:]+) ([:digit:]+) and high \
                                 to ([:alpha:]+) ([:digit:]+)")
         .unwrap();
 
-    // Parse hand-off rules and initial state
-    for line in input.lines() {
+    let mut instructions = Vec::new();
+    for (line_num, line) in input.lines().enumerate() {
         if let Some(cap) = regex_initial.captures(line) {
             let chip_id: i32 = cap.at(1).unwrap().parse().unwrap();
             let bot_id: i32 = cap.at(2).unwrap().parse().unwrap();
 
-            require_bot(&mut bots, bot_id);
-            bots[bot_id as usize].add_chip(chip_id);
+            instructions.push((line_num + 1, Instruction::Value { chip: chip_id, bot: bot_id }));
         } else if let Some(cap) = regex_bot.captures(line) {
             let bot_id: i32 = cap.at(1).unwrap().parse().unwrap();
 
@@ -92,58 +97,44 @@ fn process(input: &str, low_id: i32, high_id: i32) -> (HashMap<i32, i32>, i32) {
             let high_output_id: i32 = cap.at(5).unwrap().parse().unwrap();
             let high_output = make_dest(high_output_str, high_output_id);
 
-            require_bot(&mut bots, bot_id);
-            let bot = &mut bots[bot_id as usize];
-            bot.output_low = low_output;
-            bot.output_high = high_output;
+            instructions.push((line_num + 1,
+                               Instruction::Rule {
+                                   bot: bot_id,
+                                   low: low_output,
+                                   high: high_output,
+                               }));
         }
     }
+    instructions
+}
 
-    let mut output: HashMap<i32, i32> = HashMap::new();
-
-    // Process chip movement
-    let mut had_update = true; // halt when there are no more bots with 2 chips
-    while had_update {
-        had_update = false;
-        // Need to iterate over bot IDs to avoid borrowing the Vec in the outer loop.
-        for i in 0..bots.len() {
-
-            if bots[i].chip_low == low_id && bots[i].chip_high == high_id {
-                target_bot = i as i32;
-            }
-
-            if bots[i].chip_low >= 0 && bots[i].chip_high >= 0 {
-                had_update = true;
-                match bots[i].output_low {
-                    Dest::Bot(target_id) => {
-                        let id = bots[i].chip_low;
-                        bots[target_id as usize].add_chip(id);
-                    }
-                    Dest::Output(output_bin) => {
-                        output.insert(output_bin, bots[i].chip_low);
-                    }
-                    Dest::None => panic!("Low output unspecified"),
-                }
-                match bots[i].output_high {
-                    Dest::Bot(target_id) => {
-                        let id = bots[i].chip_high;
-                        bots[target_id as usize].add_chip(id);
-                    }
-                    Dest::Output(output_bin) => {
-                        output.insert(output_bin, bots[i].chip_high);
-                    }
-                    Dest::None => panic!("High output unspecified"),
-                }
-                bots[i].chip_low = -1;
-                bots[i].chip_high = -1;
+// Parse hand-off rules and initial state
+fn parse_bots(input: &str) -> Vec<Bot> {
+    let mut bots = Vec::new();
+    for (_, instruction) in parse_instructions(input) {
+        match instruction {
+            Instruction::Value { chip, bot } => {
+                require_bot(&mut bots, bot);
+                bots[bot as usize].add_chip(chip);
             }
-            if bots[i].chip_low >= 0 && bots[i].chip_high >= 0 {
-                panic!("Did not update chip correctly");
+            Instruction::Rule { bot, low, high } => {
+                require_bot(&mut bots, bot);
+                let bot = &mut bots[bot as usize];
+                bot.output_low = low;
+                bot.output_high = high;
             }
         }
     }
+    bots
+}
 
-    (output, target_bot)
+// Find the bot ID required to process [low_id, high_id]
+fn process(input: &str, low_id: i32, high_id: i32) -> (HashMap<i32, i32>, i32) {
+    let sim = simulation::run(&mut parse_bots(input));
+    let target_bot = sim.comparer(low_id, high_id).unwrap_or(-1);
+    // Every chip in each bin is available from Simulation::output_chips.
+    let outputs = sim.outputs.keys().map(|&bin| (bin, sim.output(bin).unwrap())).collect();
+    (outputs, target_bot)
 }
 
 fn main() {
@@ -151,6 +142,22 @@ fn main() {
     let mut file = File::open("input.txt").unwrap();
     let _ = file.read_to_string(&mut input_string);
 
+    // Check the wiring before running it. `--dot` prints it as a Graphviz graph.
+    let network = network::Network::parse(&input_string);
+    if std::env::args().any(|arg| arg == "--dot") {
+        network.write_dot(&mut io::stdout()).unwrap();
+        return;
+    }
+    // Issues that could make the run panic or loop stop it before it starts.
+    let issues = network.validate();
+    for issue in &issues {
+        println!("{}: {}", if issue.is_fatal() { "Error" } else { "Warning" }, issue);
+    }
+    if issues.iter().any(|issue| issue.is_fatal()) {
+        println!("Not running an invalid network");
+        std::process::exit(1);
+    }
+
     let results = process(&input_string, 17, 61);
     println!("Part 1: Bot {} compares chips {} and {}", results.1, 17, 61);
 
@@ -159,6 +166,36 @@ fn main() {
     let val_2 = results.0.get(&2).unwrap();
     let product = val_0 * val_1 * val_2;
     println!("Part 2: Product of output[0,1,2] is {}", product);
+
+    // The same run, queried from its event log.
+    let sim = simulation::run(&mut parse_bots(&input_string));
+    assert!(sim.comparer(61, 17) == Some(results.1));
+    assert!((0..3).map(|bin| sim.output(bin).unwrap()).product::<i32>() == product);
+    let path: Vec<String> = sim.chip_path(17).iter().map(|dest| format!("{:?}", dest)).collect();
+    println!("Chip 17 went {} in {} steps", path.join(" -> "), path.len() - 1);
+
+    // Work backwards: find a placement of the same chips that fills the
+    // first three outputs the same way.
+    let slots: Vec<i32> = network.values.iter().map(|&(_, _, bot)| bot).collect();
+    let chips: Vec<i32> = network.values.iter().map(|&(_, chip, _)| chip).collect();
+    let targets: HashMap<i32, i32> = (0..3).map(|bin| (bin, sim.output(bin).unwrap())).collect();
+    // The same bots as the input, as choosing bots too is too slow for 21 chips.
+    let placements = solver::Solver::new(&network, &chips, &targets)
+        .with_slots(&slots)
+        .solve()
+        .expect("The original placement is a solution");
+    println!("Solver placed {} chips to fill outputs 0-2", placements.len());
+
+    let mut bots = parse_bots(&input_string);
+    for bot in bots.iter_mut() {
+        bot.chip_low = -1;
+        bot.chip_high = -1;
+    }
+    for &(chip, bot) in &placements {
+        bots[bot as usize].add_chip(chip);
+    }
+    let replayed = simulation::run(&mut bots);
+    assert!(targets.iter().all(|(&bin, &chip)| replayed.output(bin) == Some(chip)));
 }
 
 #[test]
@@ -177,3 +214,24 @@ fn test() {
     println!("Bot {} compares chips {} and {}", results.1, 2, 5);
     assert!(results.1 == 2);
 }
+
+#[test]
+fn test_million_bots() {
+    // Bot i passes its low chip to output i and its high chip up the chain,
+    // where bot i + 1 is waiting with chip i + 2. Listed last bot first.
+    const BOTS: i32 = 1_000_000;
+    let mut bots = vec![Bot::new(); BOTS as usize];
+    for i in (0..BOTS).rev() {
+        let bot = &mut bots[i as usize];
+        bot.output_low = Dest::Output(i);
+        bot.output_high = if i + 1 < BOTS { Dest::Bot(i + 1) } else { Dest::Output(BOTS) };
+        bot.add_chip(i + 1);
+    }
+    bots[0].add_chip(0);
+
+    let sim = simulation::run(&mut bots);
+    assert!(sim.comparer(500_000, 500_001) == Some(500_000));
+    assert!(sim.events.len() == BOTS as usize);
+    assert!(sim.outputs.len() == BOTS as usize + 1);
+    assert!((0..BOTS + 1).all(|i| sim.output(i) == Some(i)));
+}
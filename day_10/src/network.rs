@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Write};
+
+use {parse_instructions, Dest, Instruction};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub line: usize,
+    pub low: Dest,
+    pub high: Dest,
+}
+
+// A problem found before running the factory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    // The same chip value is placed more than once.
+    DuplicateChip { chip: i32, lines: Vec<usize> },
+    // A bot has rules that disagree about where its chips go.
+    ConflictingRules { bot: i32, lines: Vec<usize> },
+    // A bot is given chips but has no rule to pass them on.
+    NoRules { bot: i32 },
+    // A bot would be given more than two chips.
+    OverSubscribed { bot: i32, chips: usize },
+    // A bot has a rule but never gets the two chips it needs to use it.
+    NeverFires { bot: i32, chips: usize },
+    // Bots that hand chips to each other in a loop.
+    Cycle { bots: Vec<i32> },
+}
+
+impl Issue {
+    // Whether running the factory anyway could go wrong. A bot that never
+    // fires just keeps its chips, but the others can panic or never finish.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(*self, Issue::NeverFires { .. })
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::DuplicateChip { chip, ref lines } => {
+                write!(f, "chip {} is placed on lines {:?}", chip, lines)
+            }
+            Issue::ConflictingRules { bot, ref lines } => {
+                write!(f, "bot {} has conflicting rules on lines {:?}", bot, lines)
+            }
+            Issue::NoRules { bot } => write!(f, "bot {} is given chips but has no rule", bot),
+            Issue::OverSubscribed { bot, chips } => {
+                write!(f, "bot {} would be given {} chips", bot, chips)
+            }
+            Issue::NeverFires { bot, chips } => {
+                write!(f, "bot {} only ever gets {} of the 2 chips it needs", bot, chips)
+            }
+            Issue::Cycle { ref bots } => write!(f, "bots {:?} pass chips in a cycle", bots),
+        }
+    }
+}
+
+// The wiring of a factory: which bot each chip starts on, and where each bot
+// sends its chips.
+pub struct Network {
+    // (line, chip, bot) for each placed chip.
+    pub values: Vec<(usize, i32, i32)>,
+    // Every rule given for each bot. The last one is the one that's used.
+    pub rules: BTreeMap<i32, Vec<Rule>>,
+}
+
+impl Network {
+    pub fn parse(input: &str) -> Network {
+        let mut values = Vec::new();
+        let mut rules: BTreeMap<i32, Vec<Rule>> = BTreeMap::new();
+        for (line, instruction) in parse_instructions(input) {
+            match instruction {
+                Instruction::Value { chip, bot } => values.push((line, chip, bot)),
+                Instruction::Rule { bot, low, high } => {
+                    rules.entry(bot).or_default().push(Rule { line, low, high })
+                }
+            }
+        }
+        Network { values, rules }
+    }
+
+    fn rule(&self, bot: i32) -> Option<&Rule> {
+        self.rules.get(&bot).and_then(|rules| rules.last())
+    }
+
+    // The bots a bot sends chips to; twice if both chips go to the same one.
+    fn targets(&self, bot: i32) -> Vec<i32> {
+        let mut targets = Vec::new();
+        if let Some(rule) = self.rule(bot) {
+            for dest in &[rule.low, rule.high] {
+                if let Dest::Bot(target) = *dest {
+                    targets.push(target);
+                }
+            }
+        }
+        targets
+    }
+
+    // Check the network without running it. Assumes each bot fires at most
+    // once, so chip counts are totals over the whole run.
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let mut chip_lines: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+        for &(line, chip, _) in &self.values {
+            chip_lines.entry(chip).or_default().push(line);
+        }
+        for (&chip, lines) in &chip_lines {
+            if lines.len() > 1 {
+                issues.push(Issue::DuplicateChip { chip, lines: lines.clone() });
+            }
+        }
+
+        for (&bot, rules) in &self.rules {
+            let first = (rules[0].low, rules[0].high);
+            if rules.iter().any(|rule| (rule.low, rule.high) != first) {
+                issues.push(Issue::ConflictingRules {
+                    bot,
+                    lines: rules.iter().map(|rule| rule.line).collect(),
+                });
+            }
+        }
+
+        // Count the chips each bot is given, firing bots as they reach two.
+        let mut chips: BTreeMap<i32, usize> = BTreeMap::new();
+        let mut ready = VecDeque::new();
+        for &(_, _, bot) in &self.values {
+            let count = chips.entry(bot).or_insert(0);
+            *count += 1;
+            if *count == 2 {
+                ready.push_back(bot);
+            }
+        }
+        while let Some(bot) = ready.pop_front() {
+            for target in self.targets(bot) {
+                let count = chips.entry(target).or_insert(0);
+                *count += 1;
+                if *count == 2 {
+                    ready.push_back(target);
+                }
+            }
+        }
+
+        for (&bot, &count) in &chips {
+            if self.rule(bot).is_none() {
+                issues.push(Issue::NoRules { bot });
+            }
+            if count > 2 {
+                issues.push(Issue::OverSubscribed { bot, chips: count });
+            }
+        }
+        for &bot in self.rules.keys() {
+            let count = chips.get(&bot).cloned().unwrap_or(0);
+            if count < 2 {
+                issues.push(Issue::NeverFires { bot, chips: count });
+            }
+        }
+
+        for bots in self.cycles() {
+            issues.push(Issue::Cycle { bots });
+        }
+        issues
+    }
+
+    // Strongly connected groups of bots that can pass chips back to
+    // themselves, found with an iterative Tarjan's algorithm.
+    fn cycles(&self) -> Vec<Vec<i32>> {
+        let mut index: HashMap<i32, usize> = HashMap::new();
+        let mut low_link: HashMap<i32, usize> = HashMap::new();
+        let mut stack = Vec::new();
+        let mut on_stack = HashMap::new();
+        let mut cycles = Vec::new();
+
+        for &root in self.rules.keys() {
+            if index.contains_key(&root) {
+                continue;
+            }
+            // (bot, targets, next target to visit)
+            let mut path = vec![(root, self.targets(root), 0)];
+            index.insert(root, index.len());
+            low_link.insert(root, index[&root]);
+            stack.push(root);
+            on_stack.insert(root, true);
+
+            while let Some(&mut (bot, ref targets, ref mut next)) = path.last_mut() {
+                let next_target = targets.get(*next).cloned();
+                *next += 1;
+                if let Some(target) = next_target {
+                    if !index.contains_key(&target) {
+                        let target_index = index.len();
+                        index.insert(target, target_index);
+                        low_link.insert(target, target_index);
+                        stack.push(target);
+                        on_stack.insert(target, true);
+                        path.push((target, self.targets(target), 0));
+                    } else if on_stack[&target] {
+                        let link = low_link[&bot].min(index[&target]);
+                        low_link.insert(bot, link);
+                    }
+                    continue;
+                }
+
+                path.pop();
+                if let Some(&(parent, _, _)) = path.last() {
+                    let link = low_link[&parent].min(low_link[&bot]);
+                    low_link.insert(parent, link);
+                }
+                if low_link[&bot] == index[&bot] {
+                    let mut group = Vec::new();
+                    loop {
+                        let member = stack.pop().unwrap();
+                        on_stack.insert(member, false);
+                        group.push(member);
+                        if member == bot {
+                            break;
+                        }
+                    }
+                    if group.len() > 1 || self.targets(bot).contains(&bot) {
+                        group.sort();
+                        cycles.push(group);
+                    }
+                }
+            }
+        }
+        cycles.sort();
+        cycles
+    }
+
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        fn node_name(dest: Dest) -> String {
+            match dest {
+                Dest::Bot(id) => format!("bot_{}", id),
+                Dest::Output(id) => format!("output_{}", id),
+                Dest::None => "none".to_string(),
+            }
+        }
+
+        writeln!(out, "digraph factory {{")?;
+        writeln!(out, "    rankdir=LR;")?;
+
+        let mut bots: Vec<i32> = self.rules.keys().cloned().collect();
+        let mut outputs = Vec::new();
+        for rule in self.rules.values().filter_map(|rules| rules.last()) {
+            for dest in &[rule.low, rule.high] {
+                match *dest {
+                    Dest::Bot(id) => bots.push(id),
+                    Dest::Output(id) => outputs.push(id),
+                    Dest::None => {}
+                }
+            }
+        }
+        bots.extend(self.values.iter().map(|&(_, _, bot)| bot));
+        bots.sort();
+        bots.dedup();
+        outputs.sort();
+        outputs.dedup();
+
+        for bot in bots {
+            writeln!(out, "    bot_{} [shape=ellipse, label=\"bot {}\"];", bot, bot)?;
+        }
+        for output in outputs {
+            writeln!(out, "    output_{} [shape=box, label=\"output {}\"];", output, output)?;
+        }
+        for &(line, chip, bot) in &self.values {
+            writeln!(out, "    value_{} [shape=plaintext, label=\"{}\"];", line, chip)?;
+            writeln!(out, "    value_{} -> bot_{};", line, bot)?;
+        }
+        for (&bot, rules) in &self.rules {
+            let rule = rules.last().unwrap();
+            writeln!(out, "    bot_{} -> {} [label=\"low\"];", bot, node_name(rule.low))?;
+            writeln!(out, "    bot_{} -> {} [label=\"high\"];", bot, node_name(rule.high))?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "value 5 goes to bot 2
+bot 2 gives low to bot 1 and high to bot 0
+value 3 goes to bot 1
+bot 1 gives low to output 1 and high to bot 0
+bot 0 gives low to output 2 and high to output 0
+value 2 goes to bot 2";
+
+    #[test]
+    fn valid_network() {
+        assert_eq!(Network::parse(EXAMPLE).validate(), vec![]);
+    }
+
+    #[test]
+    fn reports_problems() {
+        let input = "value 5 goes to bot 1
+value 5 goes to bot 2
+value 7 goes to bot 1
+bot 1 gives low to bot 3 and high to bot 2
+bot 1 gives low to bot 2 and high to bot 3
+bot 2 gives low to output 0 and high to bot 4
+bot 4 gives low to output 1 and high to output 2
+bot 5 gives low to bot 6 and high to bot 6
+bot 6 gives low to bot 5 and high to output 3";
+        let issues = Network::parse(input).validate();
+        assert_eq!(issues,
+                   vec![Issue::DuplicateChip { chip: 5, lines: vec![1, 2] },
+                        Issue::ConflictingRules { bot: 1, lines: vec![4, 5] },
+                        Issue::NoRules { bot: 3 },
+                        Issue::NeverFires { bot: 4, chips: 1 },
+                        Issue::NeverFires { bot: 5, chips: 0 },
+                        Issue::NeverFires { bot: 6, chips: 0 },
+                        Issue::Cycle { bots: vec![5, 6] }]);
+        assert_eq!(issues[1].to_string(), "bot 1 has conflicting rules on lines [4, 5]");
+        assert!(issues[1].is_fatal() && !issues[3].is_fatal());
+
+        let input = "value 1 goes to bot 0
+value 2 goes to bot 0
+value 3 goes to bot 1
+value 4 goes to bot 1
+bot 0 gives low to bot 2 and high to output 0
+bot 1 gives low to bot 2 and high to bot 2
+bot 2 gives low to output 1 and high to bot 2";
+        assert_eq!(Network::parse(input).validate(),
+                   vec![Issue::OverSubscribed { bot: 2, chips: 4 },
+                        Issue::Cycle { bots: vec![2] }]);
+    }
+
+    #[test]
+    fn dot_export() {
+        let mut dot = Vec::new();
+        Network::parse(EXAMPLE).write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph factory {\n"));
+        assert!(dot.contains("    bot_0 [shape=ellipse, label=\"bot 0\"];\n"));
+        assert!(dot.contains("    output_2 [shape=box, label=\"output 2\"];\n"));
+        assert!(dot.contains("    value_1 -> bot_2;\n"));
+        assert!(dot.contains("    bot_2 -> bot_1 [label=\"low\"];\n"));
+        assert!(dot.contains("    bot_0 -> output_0 [label=\"high\"];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
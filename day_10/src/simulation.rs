@@ -0,0 +1,184 @@
+use std::collections::hash_map::HashMap;
+use std::collections::VecDeque;
+
+use {require_bot, Bot, Dest};
+
+// One bot comparing its two chips and passing them on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HandOff {
+    pub step: usize,
+    pub bot: i32,
+    pub low: i32,
+    pub high: i32,
+    pub low_dest: Dest,
+    pub high_dest: Dest,
+}
+
+// Everything that happened in a run of the factory.
+pub struct Simulation {
+    // Hand-offs in the order they happened; events[i].step == i.
+    pub events: Vec<HandOff>,
+    // Bot each chip was placed on before the run.
+    pub start: HashMap<i32, i32>,
+    // Chips that ended up in each output bin, in the order they arrived.
+    pub outputs: HashMap<i32, Vec<i32>>,
+}
+
+// Move chips until no bot holds two. Only bots that have just received their
+// second chip are queued, so each hand-off is visited once.
+pub fn run(bots: &mut Vec<Bot>) -> Simulation {
+    let mut start = HashMap::new();
+    for (i, bot) in bots.iter().enumerate() {
+        for &chip in &[bot.chip_low, bot.chip_high] {
+            if chip >= 0 {
+                start.insert(chip, i as i32);
+            }
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut outputs: HashMap<i32, Vec<i32>> = HashMap::new();
+
+    let mut ready: VecDeque<usize> = (0..bots.len()).filter(|&i| bots[i].is_full()).collect();
+    while let Some(i) = ready.pop_front() {
+        let (chip_low, chip_high) = (bots[i].chip_low, bots[i].chip_high);
+        bots[i].chip_low = -1;
+        bots[i].chip_high = -1;
+        events.push(HandOff {
+            step: events.len(),
+            bot: i as i32,
+            low: chip_low,
+            high: chip_high,
+            low_dest: bots[i].output_low,
+            high_dest: bots[i].output_high,
+        });
+
+        for &(dest, chip) in &[(bots[i].output_low, chip_low), (bots[i].output_high, chip_high)] {
+            match dest {
+                Dest::Bot(target_id) => {
+                    require_bot(bots, target_id);
+                    let target = &mut bots[target_id as usize];
+                    target.add_chip(chip);
+                    if target.is_full() {
+                        ready.push_back(target_id as usize);
+                    }
+                }
+                Dest::Output(output_bin) => {
+                    outputs.entry(output_bin).or_default().push(chip);
+                }
+                Dest::None => panic!("Output unspecified for bot {}", i),
+            }
+        }
+    }
+
+    Simulation { events, start, outputs }
+}
+
+impl Simulation {
+    // The bot that compared chips `a` and `b`, in either order.
+    pub fn comparer(&self, a: i32, b: i32) -> Option<i32> {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        self.events
+            .iter()
+            .find(|event| event.low == low && event.high == high)
+            .map(|event| event.bot)
+    }
+
+    // Every place `chip` has been, starting with the bot it was placed on.
+    pub fn chip_path(&self, chip: i32) -> Vec<Dest> {
+        let mut path: Vec<Dest> = self.start.get(&chip).map(|&bot| Dest::Bot(bot)).into_iter().collect();
+        for event in &self.events {
+            if event.low == chip {
+                path.push(event.low_dest);
+            } else if event.high == chip {
+                path.push(event.high_dest);
+            }
+        }
+        path
+    }
+
+    // The last chip to arrive in output bin `bin`, which is the one a map of
+    // one chip per bin keeps.
+    pub fn output(&self, bin: i32) -> Option<i32> {
+        self.output_chips(bin).last().cloned()
+    }
+
+    // Every chip that ended up in output bin `bin`.
+    pub fn output_chips(&self, bin: i32) -> &[i32] {
+        self.outputs.get(&bin).map_or(&[], |chips| chips.as_slice())
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse_bots;
+
+    const EXAMPLE: &str = "value 5 goes to bot 2
+bot 2 gives low to bot 1 and high to bot 0
+value 3 goes to bot 1
+bot 1 gives low to output 1 and high to bot 0
+bot 0 gives low to output 2 and high to output 0
+value 2 goes to bot 2";
+
+    #[test]
+    fn event_log() {
+        let sim = run(&mut parse_bots(EXAMPLE));
+        assert_eq!(sim.events,
+                   vec![HandOff {
+                            step: 0,
+                            bot: 2,
+                            low: 2,
+                            high: 5,
+                            low_dest: Dest::Bot(1),
+                            high_dest: Dest::Bot(0),
+                        },
+                        HandOff {
+                            step: 1,
+                            bot: 1,
+                            low: 2,
+                            high: 3,
+                            low_dest: Dest::Output(1),
+                            high_dest: Dest::Bot(0),
+                        },
+                        HandOff {
+                            step: 2,
+                            bot: 0,
+                            low: 3,
+                            high: 5,
+                            low_dest: Dest::Output(2),
+                            high_dest: Dest::Output(0),
+                        }]);
+    }
+
+    #[test]
+    fn queries() {
+        let sim = run(&mut parse_bots(EXAMPLE));
+        assert_eq!(sim.comparer(2, 5), Some(2));
+        assert_eq!(sim.comparer(5, 2), Some(2));
+        assert_eq!(sim.comparer(3, 5), Some(0));
+        assert_eq!(sim.comparer(2, 4), None);
+
+        assert_eq!(sim.chip_path(2), vec![Dest::Bot(2), Dest::Bot(1), Dest::Output(1)]);
+        assert_eq!(sim.chip_path(5), vec![Dest::Bot(2), Dest::Bot(0), Dest::Output(0)]);
+        assert_eq!(sim.chip_path(7), vec![]);
+
+        assert_eq!(sim.output(0), Some(5));
+        assert_eq!(sim.output(1), Some(2));
+        assert_eq!(sim.output(2), Some(3));
+        assert_eq!(sim.output(3), None);
+        assert_eq!(sim.output_chips(3), &[] as &[i32]);
+    }
+
+    #[test]
+    fn shared_output() {
+        // Both of bot 0's chips go to output 0.
+        let sim = run(&mut parse_bots("value 4 goes to bot 0
+value 1 goes to bot 0
+bot 0 gives low to output 0 and high to output 0"));
+        assert_eq!(sim.output_chips(0), &[1, 4]);
+        assert_eq!(sim.output(0), Some(4));
+    }
+}
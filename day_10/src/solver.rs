@@ -0,0 +1,271 @@
+use std::collections::hash_map::HashMap;
+
+use network::{Network, Rule};
+use Dest;
+
+// Chips in motion partway through a search.
+#[derive(Clone)]
+struct State {
+    // Bots holding one chip and waiting for a second.
+    waiting: HashMap<i32, i32>,
+    // Chips each bot has been given so far, placed or passed on.
+    received: HashMap<i32, usize>,
+    outputs: HashMap<i32, i32>,
+}
+
+// Search for a way to place chips in a factory so that some output bins end
+// up with the chips wanted. By default each chip can start on any bot with a
+// rule; `with_slots` fixes which bots get chips and only chooses the values.
+pub struct Solver<'a> {
+    rules: HashMap<i32, &'a Rule>,
+    targets: &'a HashMap<i32, i32>,
+    // Every chip to place, sorted.
+    chips: Vec<i32>,
+    // Bots a chip can start on, if they aren't fixed.
+    bots: Vec<i32>,
+    // Bot for each chip to be placed, in the order they're filled.
+    slots: Option<Vec<i32>>,
+    // Distinct chip values still to place, with how many of each.
+    remaining: Vec<(i32, usize)>,
+    // (chip, bot) for each chip placed so far.
+    placed: Vec<(i32, i32)>,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(network: &'a Network, chips: &[i32], targets: &'a HashMap<i32, i32>) -> Solver<'a> {
+        let mut chips = chips.to_vec();
+        chips.sort();
+        let mut remaining: Vec<(i32, usize)> = Vec::new();
+        for &chip in &chips {
+            match remaining.last_mut() {
+                Some(&mut (value, ref mut count)) if value == chip => *count += 1,
+                _ => remaining.push((chip, 1)),
+            }
+        }
+
+        let rules: HashMap<i32, &Rule> = network.rules
+            .iter()
+            .filter_map(|(&bot, rules)| rules.last().map(|rule| (bot, rule)))
+            .collect();
+        // BTreeMap keys, so already sorted.
+        let bots = network.rules.keys().cloned().collect();
+
+        Solver {
+            rules,
+            targets,
+            chips,
+            bots,
+            slots: None,
+            remaining,
+            placed: Vec::new(),
+        }
+    }
+
+    // Only place chips on `slots`, one chip per entry.
+    pub fn with_slots(mut self, slots: &[i32]) -> Solver<'a> {
+        assert_eq!(slots.len(), self.chips.len(), "Need one chip per slot");
+
+        // Fill both slots of a bot one after the other, so it fires as soon
+        // as possible and bad choices are caught early.
+        let mut slots = slots.to_vec();
+        slots.sort();
+        self.slots = Some(slots);
+        self
+    }
+
+    // Move `chip` into `dest` and let any bots it completes fire. Fails if a
+    // target bin gets the wrong chip, or a bot can't handle what it's given.
+    fn give(&self, state: &mut State, dest: Dest, chip: i32) -> Result<(), ()> {
+        let mut moving = vec![(dest, chip)];
+        while let Some((dest, chip)) = moving.pop() {
+            match dest {
+                Dest::Bot(bot) => {
+                    // A bot only ever handles one pair of chips.
+                    let received = state.received.entry(bot).or_insert(0);
+                    *received += 1;
+                    if *received > 2 {
+                        return Err(());
+                    }
+                    match state.waiting.remove(&bot) {
+                        None => {
+                            state.waiting.insert(bot, chip);
+                        }
+                        // A bot can't compare two chips of the same value.
+                        Some(other) if other == chip => return Err(()),
+                        Some(other) => {
+                            let rule = self.rules.get(&bot).ok_or(())?;
+                            moving.push((rule.low, other.min(chip)));
+                            moving.push((rule.high, other.max(chip)));
+                        }
+                    }
+                }
+                Dest::Output(bin) => {
+                    if self.targets.get(&bin).is_some_and(|&wanted| wanted != chip) {
+                        return Err(());
+                    }
+                    state.outputs.insert(bin, chip);
+                }
+                Dest::None => return Err(()),
+            }
+        }
+        Ok(())
+    }
+
+    // Place `chip` on `bot` and carry on from there.
+    fn try_place(&mut self, state: &State, chip: i32, bot: i32) -> bool {
+        let mut next = state.clone();
+        if self.give(&mut next, Dest::Bot(bot), chip).is_err() {
+            return false;
+        }
+        self.placed.push((chip, bot));
+        if self.search(&next) {
+            return true;
+        }
+        self.placed.pop();
+        false
+    }
+
+    fn search(&mut self, state: &State) -> bool {
+        let depth = self.placed.len();
+        if depth == self.chips.len() {
+            return self.targets.keys().all(|bin| state.outputs.contains_key(bin));
+        }
+
+        if let Some(bot) = self.slots.as_ref().map(|slots| slots[depth]) {
+            // Choose the chip for the next slot.
+            for i in 0..self.remaining.len() {
+                let (chip, count) = self.remaining[i];
+                if count == 0 {
+                    continue;
+                }
+                self.remaining[i].1 -= 1;
+                let found = self.try_place(state, chip, bot);
+                self.remaining[i].1 += 1;
+                if found {
+                    return true;
+                }
+            }
+        } else {
+            // Choose the bot for the next chip. Equal chips are interchangeable,
+            // so they go on bots in increasing order.
+            let chip = self.chips[depth];
+            let first_bot = match self.placed.last() {
+                Some(&(prev_chip, prev_bot)) if prev_chip == chip => prev_bot,
+                _ => i32::MIN,
+            };
+            for i in 0..self.bots.len() {
+                let bot = self.bots[i];
+                if bot >= first_bot && self.try_place(state, chip, bot) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Returns (chip, bot) placements that give every target bin its chip, or
+    // None if there's no such placement.
+    pub fn solve(mut self) -> Option<Vec<(i32, i32)>> {
+        // Every wanted chip has to be one we have.
+        let mut wanted: Vec<i32> = self.targets.values().cloned().collect();
+        wanted.sort();
+        let mut available = self.remaining.clone();
+        for chip in wanted {
+            match available.iter_mut().find(|&&mut (value, count)| value == chip && count > 0) {
+                Some(entry) => entry.1 -= 1,
+                None => return None,
+            }
+        }
+
+        let start = State {
+            waiting: HashMap::new(),
+            received: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        if self.search(&start) {
+            Some(self.placed)
+        } else {
+            None
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {parse_bots, simulation};
+
+    // The example wiring with the chips left out.
+    const RULES: &str = "bot 2 gives low to bot 1 and high to bot 0
+bot 1 gives low to output 1 and high to bot 0
+bot 0 gives low to output 2 and high to output 0";
+
+    fn place(rules: &str, placements: &[(i32, i32)]) -> String {
+        let mut input = rules.to_string();
+        for &(chip, bot) in placements {
+            input.push_str(&format!("\nvalue {} goes to bot {}", chip, bot));
+        }
+        input
+    }
+
+    #[test]
+    fn finds_placement() {
+        let network = Network::parse(RULES);
+        let targets: HashMap<i32, i32> = [(0, 5), (2, 3)].iter().cloned().collect();
+        let placements = Solver::new(&network, &[2, 3, 5], &targets)
+            .with_slots(&[2, 2, 1])
+            .solve()
+            .unwrap();
+        assert!(placements.iter().filter(|placement| placement.1 == 2).count() == 2);
+
+        let sim = simulation::run(&mut parse_bots(&place(RULES, &placements)));
+        assert_eq!(sim.output(0), Some(5));
+        assert_eq!(sim.output(2), Some(3));
+    }
+
+    #[test]
+    fn chooses_bots() {
+        let network = Network::parse(RULES);
+
+        // Whatever goes on bot 1, it ends up comparing the smallest chip, so
+        // output 1 can't get 3 with these slots.
+        let targets: HashMap<i32, i32> = [(1, 3)].iter().cloned().collect();
+        assert_eq!(Solver::new(&network, &[2, 3, 5], &targets).with_slots(&[2, 2, 1]).solve(),
+                   None);
+
+        // But it can if 3 and 5 both start on bot 1.
+        let placements = Solver::new(&network, &[2, 3, 5], &targets).solve().unwrap();
+        let sim = simulation::run(&mut parse_bots(&place(RULES, &placements)));
+        assert_eq!(sim.output(1), Some(3));
+
+        let targets: HashMap<i32, i32> = [(0, 5), (1, 2), (2, 3)].iter().cloned().collect();
+        let placements = Solver::new(&network, &[2, 3, 5], &targets).solve().unwrap();
+        let sim = simulation::run(&mut parse_bots(&place(RULES, &placements)));
+        assert!(targets.iter().all(|(&bin, &chip)| sim.output(bin) == Some(chip)));
+    }
+
+    #[test]
+    fn proves_impossible() {
+        let network = Network::parse(RULES);
+
+        // Bot 1 only sends its smaller chip to output 1, and the largest chip
+        // is never the smaller, wherever it starts.
+        let targets: HashMap<i32, i32> = [(1, 5)].iter().cloned().collect();
+        assert_eq!(Solver::new(&network, &[2, 3, 5], &targets).solve(), None);
+
+        // Not a chip we have.
+        let targets: HashMap<i32, i32> = [(0, 7)].iter().cloned().collect();
+        assert_eq!(Solver::new(&network, &[2, 3, 5], &targets).solve(), None);
+
+        // Equal chips can't be compared, so bot 2 can't be given both 4s.
+        let targets: HashMap<i32, i32> = [(1, 4)].iter().cloned().collect();
+        assert_eq!(Solver::new(&network, &[4, 4, 9], &targets).with_slots(&[2, 2, 1]).solve(),
+                   None);
+
+        // Seven chips don't fit on three bots that each handle two at most.
+        let targets: HashMap<i32, i32> = [(0, 4)].iter().cloned().collect();
+        assert_eq!(Solver::new(&network, &[1, 2, 3, 4, 5, 6, 7], &targets).solve(), None);
+    }
+}
@@ -1,21 +1,42 @@
-
-pub use ::Material;
-
+use std::fmt;
+use std::iter;
 use std::hash::{Hash, Hasher};
 
 // Materials stored as a bit sequence, in [generator, chip] pairs, with low-bit = generator;
 // 0xA = 1010
 // 0x5 = 0101
-const CHIP_MASK: u32 = 0x_AA_AA_AA_AA_u32; // alternating, with low bit = false.
-const GEN_MASK: u32 = 0x_55_55_55_55_u32; // alternating, with low bit = true.
+// Bits are packed into 64-bit words. Words hold a whole number of pairs, so a
+// pair never straddles two words.
+const CHIP_MASK: u64 = 0x_AAAA_AAAA_AAAA_AAAA_u64; // alternating, with low bit = false.
+const GEN_MASK: u64 = 0x_5555_5555_5555_5555_u64; // alternating, with low bit = true.
+const WORD_BITS: usize = 64;
 
-#[derive(Debug,Clone,Copy)]
+#[derive(Debug,Clone)]
 pub struct BitFloor {
-    bits: u32
+    // The first word is kept inline, so floors of up to 32 pairs don't allocate.
+    first: u64,
+    // The words after the first. Never ends with a zero word.
+    rest: Vec<u64>,
 }
 impl BitFloor {
     pub fn new() -> BitFloor {
-        BitFloor { bits: 0u32 }
+        BitFloor { first: 0, rest: Vec::new() }
+    }
+
+    fn from_words<I: Iterator<Item = u64>>(mut words: I) -> BitFloor {
+        let mut floor = BitFloor { first: words.next().unwrap_or(0), rest: words.collect() };
+        floor.trim();
+        floor
+    }
+
+    fn trim(&mut self) {
+        while self.rest.last() == Some(&0) {
+            self.rest.pop();
+        }
+    }
+
+    fn words<'a>(&'a self) -> impl DoubleEndedIterator<Item = u64> + 'a {
+        iter::once(self.first).chain(self.rest.iter().cloned())
     }
 
     pub fn is_safe(&self) -> bool {
@@ -24,95 +45,110 @@ impl BitFloor {
         // * No generators
         // * No chip without it's associated generator
 
-        let gen_bits = self.bits & GEN_MASK;
-        let chip_bits = self.bits & CHIP_MASK;
+        let (mut gen_bits, mut chip_bits, mut unmatched_chips) = (0, 0, 0);
+        for word in self.words() {
+            gen_bits |= word & GEN_MASK;
+            chip_bits |= word & CHIP_MASK;
 
-        // Shifting the chip bits down by one lets us check the corresponding gens.
-        let unmatched_chips = (chip_bits >> 1) & !gen_bits;
+            // Shifting the chip bits down by one lets us check the corresponding gens.
+            unmatched_chips |= ((word & CHIP_MASK) >> 1) & !(word & GEN_MASK);
+        }
 
         chip_bits == 0 || gen_bits == 0 || unmatched_chips == 0
     }
 
-    pub fn add_gen(&mut self, item: Material) {
-        //println!("Adding gen in cell {}", item as usize * 2);
-        let bit_shift = item as usize * 2;
-        self.bits |= 0x1 << bit_shift;
+    pub fn add_gen(&mut self, material: usize) {
+        self.set(material * 2);
     }
 
-    pub fn add_chip(&mut self, item: Material) {
-        //println!("Adding chip in cell {}", item as usize * 2+1);
-        let bit_shift = item as usize * 2 + 1;
-        self.bits |= 0x1 << bit_shift;
+    pub fn add_chip(&mut self, material: usize) {
+        self.set(material * 2 + 1);
     }
 
     pub fn is_set(&self, index: usize) -> bool {
-        (self.bits & 0x1 << index) > 0
+        let bit = 0x1 << (index % WORD_BITS);
+        match index / WORD_BITS {
+            0 => self.first & bit != 0,
+            word => self.rest.get(word - 1).is_some_and(|&word| word & bit != 0),
+        }
     }
     pub fn is_empty(&self) -> bool {
-        self.bits == 0
+        self.first == 0 && self.rest.is_empty()
     }
 
     pub fn set(&mut self, index: usize) {
-        self.bits |= 0x1 << index;
+        let bit = 0x1 << (index % WORD_BITS);
+        match index / WORD_BITS {
+            0 => self.first |= bit,
+            word => {
+                if word > self.rest.len() {
+                    self.rest.resize(word, 0);
+                }
+                self.rest[word - 1] |= bit;
+            }
+        }
     }
 
     pub fn clear(&mut self, index: usize) {
-        self.bits &= !(0x1 << index);
+        let bit = 0x1 << (index % WORD_BITS);
+        match index / WORD_BITS {
+            0 => self.first &= !bit,
+            word => {
+                if word <= self.rest.len() {
+                    self.rest[word - 1] &= !bit;
+                    self.trim();
+                }
+            }
+        }
     }
 
     pub fn num_items(&self) -> u32 {
-        self.bits.count_ones()
+        self.words().map(|word| word.count_ones()).sum()
     }
 
     // True if a chip or generator is part of a matching pair.
     pub fn is_paired(&self, index: usize) -> bool {
-        let is_even = |x: usize| x & 1 == 0;
-        (is_even(index) && self.is_set(index) && self.is_set(index+1)) ||
-        (self.is_set(index) && self.is_set(index-1))
+        let gen_index = index & !1;
+        self.is_set(gen_index) && self.is_set(gen_index + 1)
     }
 
     pub fn num_pairs(&self) -> u32 {
         // Shifting the chip bits down by one lets us check the corresponding gens.
-        let gen_bits = self.bits & GEN_MASK;
-        let chip_bits = self.bits & CHIP_MASK;
-        ((chip_bits >> 1) & gen_bits).count_ones()
+        self.words().map(|word| (((word & CHIP_MASK) >> 1) & word & GEN_MASK).count_ones()).sum()
     }
 
-//    pub fn num_singles(&self) -> u32 {
-//        self.unpaired_bits().count_ones()
-//    }
-
     // All bits that are part of a [chip,gen] pair.
-    pub fn paired_bits(&self) -> u32 {
-        let gen_bits = self.bits & GEN_MASK;
-        let chip_bits = self.bits & CHIP_MASK;
-
-        // This gives us a high bit in the low-order part of each matched pair.
-        let paired_gens = (chip_bits >> 1) & gen_bits;
-        let paired_bits = paired_gens | (paired_gens << 1);
-
-//        println!("Inside paired_bits()");
-//        println!("Gen bits   : {:016b}", gen_bits);
-//        println!("Chip bits  : {:016b}", chip_bits);
-//        println!("Paired gens: {:016b}", paired_gens);
-//        println!("Paired bits: {:016b}", paired_bits);
-
-        paired_bits
+    pub fn paired_bits(&self) -> BitFloor {
+        BitFloor::from_words(self.words().map(paired_word))
     }
 
     // All bits that are *not* part of a [chip,gen] pair.
-    pub fn unpaired_bits(&self) -> u32 {
-        self.bits & !self.paired_bits()
+    pub fn unpaired_bits(&self) -> BitFloor {
+        if self.rest.is_empty() {
+            return BitFloor { first: self.first & !paired_word(self.first), rest: Vec::new() };
+        }
+        let paired = self.paired_bits();
+        BitFloor::from_words(self.words()
+            .zip(paired.words().chain(iter::repeat(0)))
+            .map(|(word, paired_word)| word & !paired_word))
     }
 }
 
+fn paired_word(word: u64) -> u64 {
+    // This gives us a high bit in the low-order part of each matched pair.
+    let paired_gens = ((word & CHIP_MASK) >> 1) & word & GEN_MASK;
+    paired_gens | (paired_gens << 1)
+}
+
 // -----------------------------------------------------------------------------
 
 impl PartialEq for BitFloor {
     fn eq(&self, other: &BitFloor) -> bool {
         // Note that {Chip,Gen} pairs are equivalent, and can be treated as identical units.
+        let (unpaired, other_unpaired) = (self.unpaired_bits(), other.unpaired_bits());
         self.num_pairs() == other.num_pairs()
-        && self.unpaired_bits() == other.unpaired_bits()
+        && unpaired.first == other_unpaired.first
+        && unpaired.rest == other_unpaired.rest
     }
 }
 impl Eq for BitFloor {}
@@ -121,10 +157,23 @@ impl Eq for BitFloor {}
 
 impl Hash for BitFloor {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        //println!("Hashing {:16b} with {} pairs and {:?} remaining bits",
-        //         self.bits, self.num_pairs(), self.unpaired_bits().count_ones());
         self.num_pairs().hash(state);
-        self.unpaired_bits().hash(state);
+        let unpaired = self.unpaired_bits();
+        unpaired.first.hash(state);
+        unpaired.rest.hash(state);
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// All the bits, highest first.
+impl fmt::Binary for BitFloor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut digits = format!("{:b}", self.rest.last().cloned().unwrap_or(self.first));
+        for word in self.words().rev().skip(1) {
+            digits.push_str(&format!("{:064b}", word));
+        }
+        f.pad_integral(true, "0b", &digits)
     }
 }
 
@@ -135,19 +184,24 @@ mod test {
     use super::*;
     use std::collections::hash_map::DefaultHasher;
 
+    const HYDROGEN: usize = 0;
+    const LITHIUM: usize = 1;
+    const POLONIUM: usize = 2;
+    const COBALT: usize = 6;
+
     #[test]
     fn floor_test_paired() {
         let mut floor = BitFloor::new();
         assert!(floor.num_pairs() == 0);
 
-        floor.add_gen(Material::Hydrogen);
-        floor.add_chip(Material::Hydrogen);
+        floor.add_gen(HYDROGEN);
+        floor.add_chip(HYDROGEN);
         assert!(floor.num_pairs() == 1);
 
-        floor.add_gen(Material::Lithium);
+        floor.add_gen(LITHIUM);
         assert!(floor.num_pairs() == 1);
 
-        floor.add_chip(Material::Lithium);
+        floor.add_chip(LITHIUM);
         assert!(floor.num_pairs() == 2);
     }
 
@@ -156,23 +210,23 @@ mod test {
         let mut floor = BitFloor::new();
         assert!(floor.num_pairs() == 0);
 
-        floor.add_gen(Material::Hydrogen);
-        println!("\nUnpaired bits: {:016b}, {} ones", floor.unpaired_bits(), floor.unpaired_bits().count_ones());
-        assert!(floor.unpaired_bits().count_ones() == 1);
-        assert!(floor.paired_bits().count_ones() == 0);
+        floor.add_gen(HYDROGEN);
+        println!("\nUnpaired bits: {:016b}, {} ones", floor.unpaired_bits(), floor.unpaired_bits().num_items());
+        assert!(floor.unpaired_bits().num_items() == 1);
+        assert!(floor.paired_bits().num_items() == 0);
 
 
-        floor.add_chip(Material::Hydrogen);
+        floor.add_chip(HYDROGEN);
         println!("Added second item to make one pair...");
-        println!("Paired bits:   {:016b}, {} bits set", floor.paired_bits(), floor.paired_bits().count_ones());
-        println!("Unpaired bits: {:016b}, {} bits set", floor.unpaired_bits(), floor.unpaired_bits().count_ones());
+        println!("Paired bits:   {:016b}, {} bits set", floor.paired_bits(), floor.paired_bits().num_items());
+        println!("Unpaired bits: {:016b}, {} bits set", floor.unpaired_bits(), floor.unpaired_bits().num_items());
         assert!(floor.num_pairs() == 1);
-        assert!(floor.paired_bits().count_ones() == 2);
-        assert!(floor.unpaired_bits().count_ones() == 0);
+        assert!(floor.paired_bits().num_items() == 2);
+        assert!(floor.unpaired_bits().num_items() == 0);
 
-        floor.add_chip(Material::Cobalt);
-        floor.add_chip(Material::Polonium);
-        assert!(floor.unpaired_bits().count_ones() == 2);
+        floor.add_chip(COBALT);
+        floor.add_chip(POLONIUM);
+        assert!(floor.unpaired_bits().num_items() == 2);
     }
 
     #[test]
@@ -180,17 +234,54 @@ mod test {
         let mut floor = BitFloor::new();
         assert!(floor.num_pairs() == 0);
 
-        floor.add_chip(Material::Lithium);
-        println!("\nUnpaired bits: {:016b}, {} ones", floor.unpaired_bits(), floor.unpaired_bits().count_ones());
-        assert!(floor.unpaired_bits().count_ones() == 1);
-        assert!(floor.paired_bits().count_ones() == 0);
+        floor.add_chip(LITHIUM);
+        println!("\nUnpaired bits: {:016b}, {} ones", floor.unpaired_bits(), floor.unpaired_bits().num_items());
+        assert!(floor.unpaired_bits().num_items() == 1);
+        assert!(floor.paired_bits().num_items() == 0);
+        assert!(!floor.is_paired(3));
+        assert!(!floor.is_paired(2));
 
         println!("Hash: {:?}", hasher(&floor));
     }
 
+    #[test]
+    fn floor_test_many_materials() {
+        // Pairs 31 and 32 sit either side of the first word boundary.
+        let mut floor = BitFloor::new();
+        floor.add_gen(31);
+        floor.add_chip(31);
+        floor.add_gen(32);
+        floor.add_chip(32);
+        floor.add_gen(100);
+        assert!(floor.num_items() == 5);
+        assert!(floor.num_pairs() == 2);
+        assert!(floor.is_paired(63) && floor.is_paired(64));
+        assert!(!floor.is_paired(200));
+        assert!(floor.is_safe());
+        assert!(format!("{:b}", floor.unpaired_bits()) == format!("1{}", "0".repeat(200)));
+
+        // A lone chip is fried by a generator in another word.
+        floor.add_chip(3);
+        assert!(!floor.is_safe());
+        floor.add_gen(3);
+        assert!(floor.is_safe());
+
+        // Clearing the high items leaves the same floor as never setting them.
+        let mut other = floor.clone();
+        other.add_chip(90);
+        other.clear(181);
+        assert!(other == floor && hasher(&other) == hasher(&floor));
+        other.clear(200);
+        assert!(other.rest.len() == 1);
+        for index in &[6, 7, 62, 63, 64, 65] {
+            other.clear(*index);
+        }
+        assert!(other.is_empty());
+    }
+
     fn hasher<T: Hash>(t: &T) -> u64 {
         let mut s = DefaultHasher::new();
         t.hash(&mut s);
         s.finish()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,608 @@
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+
+use Building;
+
+// Buildings saved as text, one floor per line, top floor first:
+//
+//     materials: hydrogen, lithium
+//     capacity: 2
+//     target: F3
+//     F3:
+//     F2: lithium generator
+//     F1: hydrogen generator
+//     F0 E: hydrogen microchip, lithium microchip
+//
+// "E" marks the elevator. The materials line fixes the order of material ids,
+// and with the capacity and target lines can be left out. Blank lines and
+// lines starting with '#' are skipped.
+//
+// Or as JSON, with floors bottom first:
+//
+//     {"materials":["hydrogen","lithium"],"capacity":2,"target":3,"elevator":0,
+//      "floors":[["hydrogen microchip","lithium microchip"],["hydrogen generator"],
+//                ["lithium generator"],[]]}
+
+// Why a saved building couldn't be read back.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum FormatError {
+    // A text line that isn't a floor or a setting. Lines count from 1.
+    BadLine { line: usize },
+    // Malformed JSON, or JSON without the fields expected, at a byte offset.
+    BadJson { pos: usize },
+    // An item that isn't "<material> generator" or "<material> microchip".
+    UnknownItem { item: String },
+    // A material name the text format can't hold: empty, padded with spaces,
+    // or with a ',', ':' or line break in it.
+    BadName { name: String },
+    // The same item on more than one floor, or twice on one.
+    DuplicateItem { item: String },
+    // A material with a generator but no microchip, or the other way round.
+    UnpairedMaterial { material: String },
+    // Floors have to be numbered from 0 with none missing.
+    MissingFloor { floor: usize },
+    // There has to be one elevator, on a floor that exists.
+    BadElevator,
+    // The target floor isn't in the building.
+    BadTarget { target: usize },
+    // The elevator has to hold at least one item.
+    BadCapacity,
+    // A microchip is left with another material's generator.
+    Unsafe,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::BadLine { line } => write!(f, "unable to parse line {}", line),
+            FormatError::BadJson { pos } => write!(f, "unable to parse JSON at offset {}", pos),
+            FormatError::UnknownItem { ref item } => write!(f, "unknown item \"{}\"", item),
+            FormatError::BadName { ref name } => write!(f, "can't save material \"{}\" as text", name),
+            FormatError::DuplicateItem { ref item } => write!(f, "{} is listed twice", item),
+            FormatError::UnpairedMaterial { ref material } => {
+                write!(f, "{} needs both a generator and a microchip", material)
+            }
+            FormatError::MissingFloor { floor } => write!(f, "floor {} is missing", floor),
+            FormatError::BadElevator => write!(f, "there has to be exactly one elevator"),
+            FormatError::BadTarget { target } => write!(f, "no target floor {}", target),
+            FormatError::BadCapacity => write!(f, "the elevator has to carry at least one item"),
+            FormatError::Unsafe => write!(f, "a microchip would be fried"),
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn description(&self) -> &str {
+        "invalid saved building"
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// The parts of a saved building, before they're checked.
+struct Layout {
+    materials: Vec<String>,
+    capacity: usize,
+    target: Option<usize>,
+    elevator: Option<usize>,
+    // Item names on each floor, bottom first.
+    floors: Vec<Option<Vec<String>>>,
+}
+
+impl Layout {
+    fn new() -> Layout {
+        Layout {
+            materials: Vec::new(),
+            capacity: 2,
+            target: None,
+            elevator: None,
+            floors: Vec::new(),
+        }
+    }
+
+    fn build(self) -> Result<Building, FormatError> {
+        let num_floors = self.floors.len();
+        let mut building = Building::new(num_floors)?;
+        if self.capacity == 0 {
+            return Err(FormatError::BadCapacity);
+        }
+        building.target_floor = self.target.unwrap_or(num_floors - 1);
+        if building.target_floor >= num_floors {
+            return Err(FormatError::BadTarget { target: building.target_floor });
+        }
+        building.elevator_idx = match self.elevator {
+            Some(floor) if floor < num_floors => floor,
+            _ => return Err(FormatError::BadElevator),
+        };
+
+        for material in &self.materials {
+            building.material_id(material);
+        }
+        for (n, items) in self.floors.into_iter().enumerate() {
+            for item in items.ok_or(FormatError::MissingFloor { floor: n })? {
+                let split = item.rfind(' ').ok_or(FormatError::UnknownItem { item: item.clone() })?;
+                let id = building.material_id(&item[..split]);
+                let index = match &item[split + 1..] {
+                    "generator" => id * 2,
+                    "microchip" => id * 2 + 1,
+                    _ => return Err(FormatError::UnknownItem { item }),
+                };
+                if building.floors.iter().any(|floor| floor.is_set(index)) {
+                    return Err(FormatError::DuplicateItem { item });
+                }
+                building.floors[n].set(index);
+            }
+        }
+
+        // There's no point carrying more than every item at once.
+        building.capacity = cmp::min(self.capacity, cmp::max(1, building.floor_size()));
+
+        for (id, material) in building.materials.iter().enumerate() {
+            if !text_name(material) {
+                return Err(FormatError::BadName { name: material.clone() });
+            }
+            let on_some_floor = |index| building.floors.iter().any(|floor| floor.is_set(index));
+            if !on_some_floor(id * 2) || !on_some_floor(id * 2 + 1) {
+                return Err(FormatError::UnpairedMaterial { material: material.clone() });
+            }
+        }
+        if !building.is_safe() {
+            return Err(FormatError::Unsafe);
+        }
+        Ok(building)
+    }
+}
+
+// Whether `name` reads back the same from the text format.
+fn text_name(name: &str) -> bool {
+    !name.is_empty() && name.trim() == name && !name.contains([',', ':', '\n', '\r'])
+}
+
+fn parse_floor_name(name: &str) -> Option<usize> {
+    name.strip_prefix('F').and_then(|number| number.parse().ok())
+}
+
+impl Building {
+    // Names of the items on floor `n`, in id order.
+    fn item_names(&self, n: usize) -> Vec<String> {
+        (0..self.floor_size())
+            .filter(|&item| self.floors[n].is_set(item))
+            .map(|item| self.item_name(item))
+            .collect()
+    }
+
+    pub fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if let Some(name) = self.materials.iter().find(|name| !text_name(name)) {
+            let error = FormatError::BadName { name: name.clone() };
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+        writeln!(out, "materials: {}", self.materials.join(", "))?;
+        writeln!(out, "capacity: {}", self.capacity)?;
+        writeln!(out, "target: F{}", self.target_floor)?;
+        for n in (0..self.floors.len()).rev() {
+            let elevator = if n == self.elevator_idx { " E" } else { "" };
+            let items = self.item_names(n);
+            if items.is_empty() {
+                writeln!(out, "F{}{}:", n, elevator)?;
+            } else {
+                writeln!(out, "F{}{}: {}", n, elevator, items.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse_text(input: &str) -> Result<Building, FormatError> {
+        // Every floor takes a line, so none can be numbered past that.
+        let max_floors = input.lines().count();
+        let mut layout = Layout::new();
+        for (line_num, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = FormatError::BadLine { line: line_num + 1 };
+            let colon = line.find(':').ok_or(bad_line.clone())?;
+            let (name, value) = (line[..colon].trim(), line[colon + 1..].trim());
+            let values: Vec<String> = if value.is_empty() {
+                Vec::new()
+            } else {
+                value.split(',').map(|value| value.trim().to_string()).collect()
+            };
+
+            match name {
+                "materials" => layout.materials = values,
+                "capacity" => layout.capacity = value.parse().map_err(|_| bad_line.clone())?,
+                "target" => layout.target = Some(parse_floor_name(value).ok_or(bad_line.clone())?),
+                _ => {
+                    let mut words = name.split_whitespace();
+                    let floor = words.next().and_then(parse_floor_name).ok_or(bad_line.clone())?;
+                    match words.next() {
+                        None => {}
+                        Some("E") if layout.elevator.is_none() => layout.elevator = Some(floor),
+                        Some("E") => return Err(FormatError::BadElevator),
+                        Some(_) => return Err(bad_line),
+                    }
+                    if words.next().is_some() || floor >= max_floors {
+                        return Err(bad_line);
+                    }
+
+                    if floor >= layout.floors.len() {
+                        layout.floors.resize(floor + 1, None);
+                    }
+                    if layout.floors[floor].is_some() {
+                        return Err(bad_line);
+                    }
+                    layout.floors[floor] = Some(values);
+                }
+            }
+        }
+        layout.build()
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        fn write_strings<W: Write>(strings: &[String], out: &mut W) -> io::Result<()> {
+            let quoted: Vec<String> = strings.iter().map(|s| format!("\"{}\"", escape(s))).collect();
+            write!(out, "[{}]", quoted.join(","))
+        }
+
+        write!(out, "{{\"materials\":")?;
+        write_strings(&self.materials, out)?;
+        write!(out,
+               ",\"capacity\":{},\"target\":{},\"elevator\":{},\"floors\":[",
+               self.capacity,
+               self.target_floor,
+               self.elevator_idx)?;
+        for n in 0..self.floors.len() {
+            if n > 0 {
+                write!(out, ",")?;
+            }
+            write_strings(&self.item_names(n), out)?;
+        }
+        writeln!(out, "]}}")
+    }
+
+    pub fn parse_json(input: &str) -> Result<Building, FormatError> {
+        let mut parser = JsonParser { input: input.as_bytes(), pos: 0 };
+        let json = parser.value()?;
+        parser.skip_space();
+        if parser.pos < input.len() {
+            return Err(FormatError::BadJson { pos: parser.pos });
+        }
+
+        // Anything missing or the wrong type is reported at the end of the input.
+        let bad = FormatError::BadJson { pos: input.len() };
+        let strings = |json: &Json| -> Option<Vec<String>> {
+            match *json {
+                Json::Array(ref values) => values.iter().map(|value| value.as_str().map(|s| s.to_string())).collect(),
+                _ => None,
+            }
+        };
+
+        let mut layout = Layout::new();
+        if let Some(materials) = json.get("materials") {
+            layout.materials = strings(materials).ok_or(bad.clone())?;
+        }
+        if let Some(capacity) = json.get("capacity") {
+            layout.capacity = capacity.as_usize().ok_or(bad.clone())?;
+        }
+        if let Some(target) = json.get("target") {
+            layout.target = Some(target.as_usize().ok_or(bad.clone())?);
+        }
+        layout.elevator = Some(json.get("elevator").and_then(Json::as_usize).ok_or(bad.clone())?);
+        layout.floors = match json.get("floors") {
+            Some(Json::Array(floors)) => {
+                floors.iter().map(|floor| strings(floor).map(Some)).collect::<Option<_>>().ok_or(bad.clone())?
+            }
+            _ => return Err(bad),
+        };
+        layout.build()
+    }
+}
+
+// Characters that need escaping in JSON strings.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 32 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// -----------------------------------------------------------------------------
+
+// Just enough JSON to read buildings back: no floats or literals.
+enum Json {
+    Number(u64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|field| field.0 == key).map(|field| &field.1),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::Str(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Json::Number(n) => Some(n as usize),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self) -> FormatError {
+        FormatError::BadJson { pos: self.pos }
+    }
+
+    fn skip_space(&mut self) {
+        while self.pos < self.input.len() && (self.input[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    // Skip spaces, then take `byte` if it's next.
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_space();
+        if self.input.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), FormatError> {
+        if self.eat(byte) { Ok(()) } else { Err(self.error()) }
+    }
+
+    // Values separated by commas up to `close`, after the opening bracket.
+    fn list<T, F>(&mut self, close: u8, mut item: F) -> Result<Vec<T>, FormatError>
+        where F: FnMut(&mut Self) -> Result<T, FormatError>
+    {
+        let mut items = Vec::new();
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, FormatError> {
+        self.skip_space();
+        match self.input.get(self.pos) {
+            Some(&b'{') => {
+                self.pos += 1;
+                let fields = self.list(b'}', |parser| {
+                    parser.skip_space();
+                    let key = parser.string()?;
+                    parser.expect(b':')?;
+                    Ok((key, parser.value()?))
+                })?;
+                Ok(Json::Object(fields))
+            }
+            Some(&b'[') => {
+                self.pos += 1;
+                Ok(Json::Array(self.list(b']', |parser| parser.value())?))
+            }
+            Some(&b'"') => Ok(Json::Str(self.string()?)),
+            Some(byte) if byte.is_ascii_digit() => {
+                let start = self.pos;
+                while self.input.get(self.pos).is_some_and(|byte| byte.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let digits = String::from_utf8_lossy(&self.input[start..self.pos]);
+                digits.parse().map(Json::Number).map_err(|_| FormatError::BadJson { pos: start })
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        if self.input.get(self.pos) != Some(&b'"') {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.input.get(self.pos) {
+                None => return Err(self.error()),
+                Some(&b'"') => break,
+                Some(&b'\\') => {
+                    let escaped = match self.input.get(self.pos + 1) {
+                        Some(&b'"') => '"',
+                        Some(&b'\\') => '\\',
+                        Some(&b'/') => '/',
+                        Some(&b'n') => '\n',
+                        Some(&b't') => '\t',
+                        Some(&b'u') => {
+                            let hex = self.input.get(self.pos + 2..self.pos + 6).ok_or(self.error())?;
+                            let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16)
+                                .map_err(|_| self.error())?;
+                            self.pos += 4;
+                            ::std::char::from_u32(code).ok_or(self.error())?
+                        }
+                        _ => return Err(self.error()),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                    self.pos += 2;
+                }
+                Some(&byte) => {
+                    bytes.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+        self.pos += 1;
+        String::from_utf8(bytes).map_err(|_| self.error())
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use moves::Direction;
+    use {process_astar, read_input};
+
+    const EXAMPLE: &str = "The first floor contains a hydrogen-compatible microchip and a \
+                           lithium-compatible microchip.\nThe second floor contains a hydrogen \
+                           generator.\nThe third floor contains a lithium generator.\nThe fourth \
+                           floor contains nothing relevant.\n";
+
+    const EXAMPLE_TEXT: &str = "materials: hydrogen, lithium
+capacity: 2
+target: F3
+F3:
+F2: lithium generator
+F1: hydrogen generator
+F0 E: hydrogen microchip, lithium microchip
+";
+
+    fn text(building: &Building) -> String {
+        let mut out = Vec::new();
+        building.write_text(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn json(building: &Building) -> String {
+        let mut out = Vec::new();
+        building.write_json(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let building = read_input(EXAMPLE).unwrap();
+        assert_eq!(text(&building), EXAMPLE_TEXT);
+
+        let parsed = Building::parse_text(EXAMPLE_TEXT).unwrap();
+        assert!(parsed == building);
+        assert!(parsed.materials == building.materials);
+        assert_eq!(text(&parsed), EXAMPLE_TEXT);
+
+        // Partway through, with the elevator up a floor.
+        let moved = building.try_move(Direction::Up, &[1]).unwrap();
+        let parsed = Building::parse_text(&text(&moved)).unwrap();
+        assert!(parsed == moved && parsed.elevator_idx == 1);
+        assert!(process_astar(&parsed, 20) == 10);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut building = read_input(EXAMPLE).unwrap();
+        building.capacity = 3;
+        building.target_floor = 1;
+        assert_eq!(json(&building),
+                   "{\"materials\":[\"hydrogen\",\"lithium\"],\"capacity\":3,\"target\":1,\
+                    \"elevator\":0,\"floors\":[[\"hydrogen microchip\",\"lithium microchip\"],\
+                    [\"hydrogen generator\"],[\"lithium generator\"],[]]}\n");
+
+        let parsed = Building::parse_json(&json(&building)).unwrap();
+        assert!(parsed == building);
+        assert!(parsed.capacity == 3 && parsed.target_floor == 1);
+        assert_eq!(json(&parsed), json(&building));
+
+        // Names that need escaping survive.
+        let mut odd = Building::new(2).unwrap();
+        odd.add_gen(1, "\"quoted\" \\ élan");
+        odd.add_chip(1, "\"quoted\" \\ élan");
+        odd.elevator_idx = 1;
+        assert!(Building::parse_json(&json(&odd)).unwrap().materials == odd.materials);
+    }
+
+    #[test]
+    fn hand_written() {
+        // Comments, spaces, floors out of order, new materials and defaults.
+        let input = "# Two floors\n\
+                     F0 E: unobtainium microchip ,unobtainium generator\n\
+                     \n\
+                     F1:   \n";
+        let building = Building::parse_text(input).unwrap();
+        assert!(building.floors.len() == 2 && building.target_floor == 1 && building.capacity == 2);
+        assert!(process_astar(&building, 10) == 1);
+
+        let input = "{ \"elevator\": 1, \"floors\": [ [], [\"a generator\", \"a microchip\"] ] }";
+        let building = Building::parse_json(input).unwrap();
+        assert!(building.is_final());
+        assert!(process_astar(&building, 10) == 0);
+    }
+
+    #[test]
+    fn errors() {
+        let parse = |input: &str| Building::parse_text(input).map(|_| ());
+        assert_eq!(parse("F0 E: a generator, a microchip\nnonsense"), Err(FormatError::BadLine { line: 2 }));
+        assert_eq!(parse("F0 E: a generator, a chip"), Err(FormatError::UnknownItem { item: "a chip".to_string() }));
+        assert_eq!(parse("F1 E: a generator, a microchip\nF0: a generator"),
+                   Err(FormatError::DuplicateItem { item: "a generator".to_string() }));
+        assert_eq!(parse("F0 E: a generator"), Err(FormatError::UnpairedMaterial { material: "a".to_string() }));
+        assert_eq!(parse("F2 E: a generator, a microchip\nF1:\n# F0 left out"),
+                   Err(FormatError::MissingFloor { floor: 0 }));
+        // Floors numbered past the lines there are.
+        assert_eq!(parse("F2 E: a generator, a microchip\nF0:"),
+                   Err(FormatError::BadLine { line: 1 }));
+        assert_eq!(parse("F18446744073709551615 E: a generator, a microchip"),
+                   Err(FormatError::BadLine { line: 1 }));
+        assert_eq!(parse("F99999999999 E: a generator, a microchip"),
+                   Err(FormatError::BadLine { line: 1 }));
+        assert_eq!(parse("F0: a generator, a microchip"), Err(FormatError::BadElevator));
+        assert_eq!(parse("F0 E: a generator, a microchip\nF1 E:"), Err(FormatError::BadElevator));
+        assert_eq!(parse("target: F4\nF0 E: a generator, a microchip"), Err(FormatError::BadTarget { target: 4 }));
+        assert_eq!(parse("capacity: 0\nF0 E: a generator, a microchip"), Err(FormatError::BadCapacity));
+        // Capacities past the number of items are cut down to it.
+        let input = "capacity: 18446744073709551615\nF0 E: a generator, a microchip\nF1:";
+        let building = Building::parse_text(input).unwrap();
+        assert!(building.capacity == 2 && process_astar(&building, 10) == 1);
+        assert_eq!(parse("F1: a generator, b microchip\nF0 E: b generator, a microchip"), Err(FormatError::Unsafe));
+
+        let parse = |input: &str| Building::parse_json(input).map(|_| ());
+        assert_eq!(parse("{\"elevator\":0,\"floors\":[[\"a generator\",\"a microchip\"]]"),
+                   Err(FormatError::BadJson { pos: 54 }));
+        assert_eq!(parse("{\"floors\":[[\"a generator\",\"a microchip\"]]}"),
+                   Err(FormatError::BadJson { pos: 42 }));
+        assert_eq!(FormatError::BadJson { pos: 3 }.to_string(), "unable to parse JSON at offset 3");
+
+        // Names that wouldn't read back from text.
+        for name in &["a,b", "a:b", " a", ""] {
+            let input = format!("{{\"elevator\":0,\"floors\":[[\"{0} generator\",\"{0} microchip\"]]}}",
+                                name);
+            assert_eq!(parse(&input), Err(FormatError::BadName { name: name.to_string() }));
+        }
+        let mut building = Building::new(1).unwrap();
+        building.add_gen(0, "a,b");
+        building.add_chip(0, "a,b");
+        let err = building.write_text(&mut Vec::new()).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,15 +1,14 @@
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
-use std::str::FromStr;
-use std::cmp;
+use std::error::Error;
+use std::path::Path;
 use std::fmt;
-use std::hash::{Hash, Hasher};
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
-
-#[macro_use]
-extern crate lazy_static;
+use std::rc::Rc;
 
 extern crate regex;
 use regex::Regex;
@@ -17,62 +16,83 @@ use regex::Regex;
 mod bit_floor;
 use bit_floor::BitFloor;
 
-#[derive(Debug,PartialEq,Eq,Clone,Copy)]
-pub enum Material {
-    Hydrogen = 0,
-    Lithium = 1,
-    Polonium = 2,
-    Thulium = 3,
-    Promethium = 4,
-    Ruthenium = 5,
-    Cobalt = 6,
-    Elerium = 7,
-    Dilithium = 8,
-}
-impl FromStr for Material {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Material, &'static str> {
-        match s {
-            "hydrogen" => Ok(Material::Hydrogen),
-            "lithium" => Ok(Material::Lithium),
-            "polonium" => Ok(Material::Polonium),
-            "thulium" => Ok(Material::Thulium),
-            "promethium" => Ok(Material::Promethium),
-            "ruthenium" => Ok(Material::Ruthenium),
-            "cobalt" => Ok(Material::Cobalt),
-            "elerium" => Ok(Material::Elerium),
-            "dilithium" => Ok(Material::Dilithium),
-            _ => Err("Unknown material string"),
-        }
-    }
-}
+mod moves;
+use moves::{Direction, Move, Trail};
+
+mod state_key;
+use state_key::StateKey;
+
+mod format;
+use format::FormatError;
+
+mod search;
+use search::SearchStats;
 
 // -----------------------------------------------------------------------------
 
-// Materials stored as a bool array, in [generator, chip] pairs.
+// Materials stored as a bit array, in [generator, chip] pairs.
 // Gen  location = material_id*2
 // Chip location = material_id*2 + 1,
-const MATERIAL_TYPES: usize = 9;
-const FLOOR_SIZE: usize = MATERIAL_TYPES*2;
+// Material ids are handed out in the order names are first seen.
 
-// -----------------------------------------------------------------------------
-
-#[derive(Debug,Clone,Copy)]
+#[derive(Debug,Clone)]
 struct Building {
-    floors: [BitFloor; 4],
+    floors: Vec<BitFloor>,
     elevator_idx: usize,
     depth: usize,
+    // Floor everything has to be moved to.
+    target_floor: usize,
+    // Most items the elevator can carry at once.
+    capacity: usize,
+    // How this state was reached.
+    trail: Option<Rc<Trail>>,
+    // Name of each material, indexed by id. Shared between search states.
+    materials: Rc<Vec<String>>,
 }
 impl Building {
-    fn new() -> Building {
-        Building {
-            floors: [BitFloor::new(); 4],
+    // An empty building, with the elevator carrying two items to the top floor.
+    // There has to be at least one floor.
+    fn new(num_floors: usize) -> Result<Building, FormatError> {
+        if num_floors == 0 {
+            return Err(FormatError::MissingFloor { floor: 0 });
+        }
+        Ok(Building {
+            floors: vec![BitFloor::new(); num_floors],
             elevator_idx: 0,
-            depth: 0
+            depth: 0,
+            target_floor: num_floors - 1,
+            capacity: 2,
+            trail: None,
+            materials: Rc::new(Vec::new()),
+        })
+    }
+
+    // The id of the named material, adding it if it's new.
+    fn material_id(&mut self, name: &str) -> usize {
+        match self.materials.iter().position(|material| material == name) {
+            Some(id) => id,
+            None => {
+                Rc::make_mut(&mut self.materials).push(name.to_string());
+                self.materials.len() - 1
+            }
         }
     }
 
+    fn add_gen(&mut self, floor: usize, material: &str) {
+        let id = self.material_id(material);
+        self.floors[floor].add_gen(id);
+    }
+
+    fn add_chip(&mut self, floor: usize, material: &str) {
+        let id = self.material_id(material);
+        self.floors[floor].add_chip(id);
+    }
+
+    // Number of item slots on each floor.
+    fn floor_size(&self) -> usize {
+        self.materials.len() * 2
+    }
+
     fn item_exists(&self, item: usize) -> bool {
         self.floors[self.elevator_idx].is_set(item)
     }
@@ -82,114 +102,145 @@ impl Building {
     }
 
     fn is_safe(&self) -> bool {
-        self.floors.iter().all(|&floor| floor.is_safe())
+        self.floors.iter().all(|floor| floor.is_safe())
     }
 
-    // Number of items-steps to reach the top floor.
+    // Number of items-steps to reach the target floor.
     fn distance(&self) -> u32 {
-        self.floors[2].num_items() * 1 +
-        self.floors[1].num_items() * 2 +
-        self.floors[0].num_items() * 3
+        self.floors
+            .iter()
+            .enumerate()
+            .map(|(n, floor)| floor.num_items() * (n as isize - self.target_floor as isize).unsigned_abs() as u32)
+            .sum()
     }
 
-    // Check if everything is on the fourth floor (floors 0-2 are empty)
+    // A lower bound on the steps left. Every item on the wrong side of a
+    // floor boundary has to be carried across it, and each step crosses
+    // just one boundary.
+    fn min_steps(&self) -> usize {
+        let mut steps = 0;
+        let mut below = 0;
+        for floor in &self.floors[..self.target_floor] {
+            below += floor.num_items() as usize;
+            steps += crossing_steps(below, self.capacity);
+        }
+        let mut above = 0;
+        for floor in self.floors[self.target_floor+1..].iter().rev() {
+            above += floor.num_items() as usize;
+            steps += crossing_steps(above, self.capacity);
+        }
+        steps
+    }
+
+    // Check if everything is on the target floor.
     fn is_final(&self) -> bool {
-        self.floors[0].is_empty()
-        && self.floors[1].is_empty()
-        && self.floors[2].is_empty()
+        self.floors
+            .iter()
+            .enumerate()
+            .all(|(n, floor)| n == self.target_floor || floor.is_empty())
     }
 
-    // Try to move one or more items up a floor and return the new building state.
-    fn try_move_up(&self, item_1: usize, item_2: usize) -> Option<Building> {
-        if self.elevator_idx < 3 && self.floors[self.elevator_idx].is_set(item_1) &&
-           self.floors[self.elevator_idx].is_set(item_2) {
-            let mut next = self.clone();
+    // Carry one or more items a floor up or down and return the new building
+    // state, whether or not it's safe. None if the elevator can't make the trip.
+    fn carry(&self, direction: Direction, items: &[usize]) -> Option<Building> {
+        let from = self.elevator_idx;
+        let to = match direction {
+            Direction::Up if from + 1 < self.floors.len() => from + 1,
+            Direction::Down if from > 0 => from - 1,
+            _ => return None,
+        };
+        if items.is_empty() || items.len() > self.capacity ||
+           !items.iter().all(|&item| self.floors[from].is_set(item)) {
+            return None;
+        }
 
-            next.floors[next.elevator_idx].clear(item_1);
-            next.floors[next.elevator_idx + 1].set(item_1);
+        let mut next = self.clone();
+        for &item in items {
+            next.floors[from].clear(item);
+            next.floors[to].set(item);
+        }
+        next.elevator_idx = to;
+        next.depth = self.depth+1;
+        next.trail = Some(Rc::new(Trail {
+            step: Move { direction, items: items.to_vec() },
+            prev: self.trail.clone(),
+        }));
+        Some(next)
+    }
 
-            next.floors[next.elevator_idx].clear(item_2);
-            next.floors[next.elevator_idx + 1].set(item_2);
+    // Like carry(), but only if the new state is safe.
+    fn try_move(&self, direction: Direction, items: &[usize]) -> Option<Building> {
+        self.carry(direction, items).filter(|next| next.is_safe())
+    }
 
-            next.elevator_idx += 1;
-            next.depth = self.depth+1;
-            if next.is_safe() {
-                Some(next)
-            } else {
-                None
-            }
-        } else {
-            None
+    // The moves made to reach this state, first move first.
+    fn moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut trail = self.trail.as_ref();
+        while let Some(node) = trail {
+            moves.push(node.step.clone());
+            trail = node.prev.as_ref();
         }
+        moves.reverse();
+        moves
     }
 
-    // Try to move one or more items up a floor and return the new building state.
-    fn try_move_down(&self, item_1: usize, item_2: usize) -> Option<Building> {
-        if self.elevator_idx > 0 && self.floors[self.elevator_idx].is_set(item_1) &&
-           self.floors[self.elevator_idx].is_set(item_2) {
-            let mut next = self.clone();
-
-            next.floors[next.elevator_idx].clear(item_1);
-            next.floors[next.elevator_idx - 1].set(item_1);
+    // "thulium generator", "cobalt microchip", ...
+    fn item_name(&self, item: usize) -> String {
+        format!("{} {}", self.materials[item / 2], if item & 1 == 0 {"generator"} else {"microchip"})
+    }
 
-            next.floors[next.elevator_idx].clear(item_2);
-            next.floors[next.elevator_idx - 1].set(item_2);
+    fn describe_move(&self, step: &Move) -> String {
+        let items: Vec<String> = step.items.iter().map(|&item| self.item_name(item)).collect();
+        format!("{:?} with {}", step.direction, items.join(" and "))
+    }
+}
 
-            next.elevator_idx -= 1;
-            next.depth = self.depth+1;
-            if next.is_safe() {
-                Some(next)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+// Fewest trips to get `items` across one floor boundary. Each trip over
+// carries at most `capacity`, and every trip back has to bring at least one
+// item with it, so each round trip after the first gets `capacity - 1` over.
+fn crossing_steps(items: usize, capacity: usize) -> usize {
+    if items == 0 {
+        0
+    } else if items <= capacity {
+        1
+    } else if capacity < 2 {
+        items
+    } else {
+        1 + 2 * (items - capacity).div_ceil(capacity - 1)
     }
 }
 
 impl fmt::Display for Building {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for i in 0..4 {
-            let n = 3-i;
+        for n in (0..self.floors.len()).rev() {
             write!(f, "F{} {} ", n, if n==self.elevator_idx {"E"} else {"."})?;
-            for item in 0..FLOOR_SIZE {
+            for item in 0..self.floor_size() {
                 write!(f, "{}", if self.floors[n].is_set(item) {"# "} else {". "})?;
             }
-            writeln!(f, "")?;
+            writeln!(f)?;
         }
         writeln!(f, "Distance {}", self.distance())
         //write!(f, "")
     }
 }
 
+// States are equal if they play out the same way, whatever the depth.
 impl PartialEq for Building {
     fn eq(&self, other: &Building) -> bool {
-        self.elevator_idx == other.elevator_idx &&
-        self.floors[0] == other.floors[0] &&
-        self.floors[1] == other.floors[1] &&
-        self.floors[2] == other.floors[2] &&
-        self.floors[3] == other.floors[3]
+        self.state_key() == other.state_key()
     }
 }
 
 impl Eq for Building {}
 
-impl Hash for Building {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.elevator_idx.hash(state);
-        self.floors[0].hash(state);
-        self.floors[1].hash(state);
-        self.floors[2].hash(state);
-        self.floors[3].hash(state);
-    }
-}
-
-// For sorting in a priority queue.
+// For sorting in a priority queue, by the least number of steps a solution
+// through this state could take.
 impl Ord for Building {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // Normally "(self.distance()).cmp(&other.distance())", but inverted for a min-heap.
-        (other.distance()).cmp(&self.distance())
+        // Inverted for a min-heap. On ties the deeper state goes first, as it's nearer the end.
+        (other.depth + other.min_steps()).cmp(&(self.depth + self.min_steps()))
+            .then(self.depth.cmp(&other.depth))
     }
 }
 
@@ -201,19 +252,91 @@ impl PartialOrd for Building {
 
 // -----------------------------------------------------------------------------
 
-const NO_PATH: usize = std::usize::MAX - 1;
+// Every way of picking `size` items from `items`, in order.
+fn combinations(items: &[usize], size: usize) -> Vec<Vec<usize>> {
+    if size == 0 {
+        return vec![Vec::new()];
+    }
+    let mut result = Vec::new();
+    for (i, &item) in items.iter().enumerate() {
+        for mut rest in combinations(&items[i+1..], size - 1) {
+            rest.insert(0, item);
+            result.push(rest);
+        }
+    }
+    result
+}
+
+// Safe states reachable with one elevator trip.
+fn next_states(current: &Building) -> Vec<Building> {
+    trips(current)
+}
+
+// Safe states one elevator trip before `current`. Every trip can be made in
+// reverse, and both searches pick trips the same way, so these are the states
+// whose next_states() lead to `current`.
+fn predecessors(current: &Building) -> Vec<Building> {
+    trips(current)
+}
+
+fn trips(current: &Building) -> Vec<Building> {
+    // {Chip,Gen} pairs on the elevator's floor are interchangeable, and a trip
+    // can't touch more pairs than it carries items, so only pick from that many.
+    let max_pairs = current.capacity;
+    let mut items = Vec::new();
+    let mut pairs_seen = 0;
+    for item in 0..current.floor_size() {
+        if !current.item_exists(item) { continue; }
+        if current.item_paired(item) {
+            if item & 1 == 0 {
+                pairs_seen += 1;
+            }
+            if pairs_seen > max_pairs { continue; }
+        }
+        items.push(item);
+    }
+
+    // Picking different pairs the same way gives the same state, so keep one.
+    let mut seen = HashSet::new();
+    let mut next_states = Vec::new();
+    for size in 1..current.capacity+1 {
+        for selection in combinations(&items, size) {
+            for &direction in &[Direction::Up, Direction::Down] {
+                if let Some(next) = current.try_move(direction, &selection) {
+                    if seen.insert(next.state_key()) {
+                        next_states.push(next);
+                    }
+                }
+            }
+        }
+    }
+    next_states
+}
+
+const NO_PATH: usize = usize::MAX - 1;
 const MAX_DEPTH: usize = 200;
 
-// Returns the number of steps for everything to reach floor 4 (NO_PATH on failure)
+// Returns the number of steps for everything to reach the target floor (NO_PATH on failure)
 fn process_bfs(initial: &Building, max_depth: usize) -> usize {
+    let (solution, stats) = solve_bfs(initial, max_depth);
+    println!("BFS {}", stats);
+    solution.map_or(NO_PATH, |solution| solution.depth)
+}
+
+// Returns a final state with the fewest steps, whose moves() solve `initial`.
+fn solve_bfs(initial: &Building, max_depth: usize) -> (Option<Building>, SearchStats) {
+    let mut stats = SearchStats::new();
 
     let mut best_path = NO_PATH;
-    let mut history: HashSet<Building> = HashSet::new();
+    let mut best_state = None;
+    // Fewest steps to each state seen.
+    let mut history: HashMap<StateKey, usize> = HashMap::new();
 
     let mut queue = VecDeque::new();
-    queue.push_back(*initial);
+    queue.push_back(initial.clone());
 
     while let Some(current) = queue.pop_front() {
+        stats.hold(history.len() + queue.len() + 1);
         if current.depth > max_depth || current.depth > best_path {
             continue;
         }
@@ -221,121 +344,231 @@ fn process_bfs(initial: &Building, max_depth: usize) -> usize {
         // Skip already visited states.
         // NOTE: if the current state has a shorter path then the previous
         // one we need to process it to ensure finding a shortest path.
-        if let Some(previous_state) = history.replace(current) {
-            if previous_state.depth <= current.depth {
+        if let Some(previous_depth) = history.insert(current.state_key(), current.depth) {
+            if previous_depth <= current.depth {
                 continue;
             }
         }
 
+        stats.visited += 1;
+
         // Check for goal state
-        if current.is_final() {
-            best_path = cmp::min(best_path, current.depth);
+        if current.is_final() && current.depth < best_path {
+            best_path = current.depth;
+            best_state = Some(current.clone());
         }
 
-        let mut can_move_single_down = false;
-        for item_1 in 0..FLOOR_SIZE {
-            if current.item_exists(item_1) {
-                // Only try moving one item down
-                if let Some(next) = current.try_move_down(item_1, item_1) {
-                    queue.push_back(next);
-                    can_move_single_down = true;
-                }
-            }
+        for next in next_states(&current) {
+            queue.push_back(next);
         }
+    }
 
-        let mut first_pair_idx = None;
-        for item_1 in 0..FLOOR_SIZE {
-            if !current.item_exists(item_1) { continue; }
+    (best_state, stats)
+}
 
-            // Only try moving one item down
-            if let Some(next) = current.try_move_down(item_1, item_1) {
-                queue.push_back(next);
-                can_move_single_down = true;
-            }
+// A* search, expanding the states with the smallest lower bound on their
+// total steps first. Returns the same step counts as process_bfs.
+fn process_astar(initial: &Building, max_depth: usize) -> usize {
+    let (solution, stats) = solve_astar(initial, max_depth);
+    println!("A* {}", stats);
+    solution.map_or(NO_PATH, |solution| solution.depth)
+}
 
-            if first_pair_idx.is_none() && current.item_paired(item_1) {
-                first_pair_idx = Some(item_1);
-            }
+fn solve_astar(initial: &Building, max_depth: usize) -> (Option<Building>, SearchStats) {
+    let mut stats = SearchStats::new();
 
-            // Skip moving paired items beyond the first pair.
-            if let Some(idx)= first_pair_idx {
-                if current.item_paired(item_1) && item_1 > idx+1 {
-                    continue;
-                }
-            }
+    // Fewest steps to each state expanded.
+    let mut history: HashMap<StateKey, usize> = HashMap::new();
 
-            for item_2 in item_1..FLOOR_SIZE {
-                if !current.item_exists(item_2) { continue; }
+    let mut queue = BinaryHeap::new();
+    queue.push(initial.clone());
 
-                // Skip moving paired items beyond the first pair.
-                if let Some(idx)= first_pair_idx {
-                    if current.item_paired(item_2) && item_2 > idx+1 {
-                        continue;
-                    }
-                }
+    while let Some(current) = queue.pop() {
+        stats.hold(history.len() + queue.len() + 1);
+        if current.depth > max_depth {
+            continue;
+        }
 
-                // Try moving both items up or down
-                if let Some(next) = current.try_move_up(item_1, item_2) {
-                    queue.push_back(next);
-                }
+        // min_steps() isn't always consistent, so a state can be reached
+        // again with fewer steps and has to be expanded again.
+        let key = current.state_key();
+        if history.get(&key).is_some_and(|&previous_depth| previous_depth <= current.depth) {
+            continue;
+        }
 
-                // Try moving two items down if it wasn't safe to move a single one.
-                if !can_move_single_down && item_1 != item_2 {
-                    if let Some(next) = current.try_move_down(item_1, item_2) {
-                        queue.push_back(next);
-                    }
-                }
+        stats.visited += 1;
+
+        // The first goal state out of the queue has the fewest steps.
+        if current.is_final() {
+            return (Some(current), stats);
+        }
+
+        for next in next_states(&current) {
+            if history.get(&next.state_key()).is_none_or(|&previous_depth| previous_depth > next.depth) {
+                queue.push(next);
             }
         }
+        history.insert(key, current.depth);
     }
 
-    println!("BFS terminated after searching {} unique nodes", history.len());
-
-    best_path
+    (None, stats)
 }
 
-
-fn read_input(input: &str) -> Building {
+fn read_input(input: &str) -> Result<Building, FormatError> {
     let regex_chip = Regex::new("([:alpha:]+)-compatible").unwrap();
     let regex_gen = Regex::new("([:alpha:]+) generator").unwrap();
 
-    let mut building = Building::new();
+    // One floor per line.
+    let mut building = Building::new(input.lines().count())?;
     for (floor_idx, line) in input.lines().enumerate() {
 
         for cap in regex_chip.captures_iter(line) {
-            let chip_type = cap.at(1).unwrap().parse::<Material>().expect("Unknown chip material");
+            let chip_type = cap.at(1).unwrap();
             //println!("Floor {}: {:?} type microchip", floor_idx, chip_type);
-            building.floors[floor_idx].add_chip(chip_type);
+            building.add_chip(floor_idx, chip_type);
         }
 
         for cap in regex_gen.captures_iter(line) {
-            let gen_type = cap.at(1).unwrap().parse::<Material>().expect("Unknown gen material");
+            let gen_type = cap.at(1).unwrap();
             //println!("Floor {}: {:?} type generator", floor_idx, gen_type);
-            building.floors[floor_idx].add_gen(gen_type);
+            building.add_gen(floor_idx, gen_type);
+        }
+    }
+    if !building.is_safe() {
+        return Err(FormatError::Unsafe);
+    }
+    Ok(building)
+}
+
+// Check the memory-saving searches agree with A*.
+fn compare_searches(building: &Building, steps: usize) {
+    let (bidirectional_steps, bidirectional_stats) = search::solve_bidirectional(building, MAX_DEPTH);
+    println!("Bidirectional BFS {}", bidirectional_stats);
+    assert!(bidirectional_steps == steps);
+
+    let (ida_steps, ida_stats) = search::solve_ida(building, MAX_DEPTH);
+    println!("IDA* {}", ida_stats);
+    assert!(ida_steps == steps);
+}
+
+fn print_moves(initial: &Building, moves: &[Move]) {
+    let mut current = initial.clone();
+    for (i, step) in moves.iter().enumerate() {
+        current = current.carry(step.direction, &step.items).expect("Moves can be made");
+        println!("Move {}: {}\n{}", i + 1, current.describe_move(step), current);
+    }
+}
+
+// The argument after `flag`, if it was given.
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next().and(args.next())
+}
+
+// Read a building saved as JSON (starting with '{') or as text.
+fn load_building(path: &str) -> Result<Building, Box<dyn Error>> {
+    let mut input = String::new();
+    File::open(path)?.read_to_string(&mut input)?;
+    let building = if input.trim_start().starts_with('{') {
+        Building::parse_json(&input)?
+    } else {
+        Building::parse_text(&input)?
+    };
+    Ok(building)
+}
+
+// Write the state before each move and the final one to `dir`, as both
+// step_N.txt and step_N.json.
+fn save_states(dir: &str, initial: &Building, moves: &[Move]) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut current = initial.clone();
+    for i in 0..moves.len() + 1 {
+        if i > 0 {
+            current = current.carry(moves[i - 1].direction, &moves[i - 1].items).expect("Moves can be made");
+        }
+        current.write_text(&mut File::create(Path::new(dir).join(format!("step_{}.txt", i)))?)?;
+        current.write_json(&mut File::create(Path::new(dir).join(format!("step_{}.json", i)))?)?;
+    }
+    Ok(())
+}
+
+// Solve `building` with extra empty floors above it and bigger elevators.
+fn study(building: &Building) {
+    for num_floors in building.floors.len()..building.floors.len()+3 {
+        for capacity in 2..5 {
+            let mut variant = building.clone();
+            variant.floors.resize(num_floors, BitFloor::new());
+            variant.target_floor = num_floors - 1;
+            variant.capacity = capacity;
+
+            let steps = process_astar(&variant, MAX_DEPTH);
+            if steps < NO_PATH {
+                println!("{} floors, capacity {}: steps = {}", num_floors, capacity, steps);
+            } else {
+                println!("{} floors, capacity {}: no path found within {} steps", num_floors, capacity, MAX_DEPTH);
+            }
         }
     }
-    assert!(building.is_safe());
-    building
 }
 
 // -----------------------------------------------------------------------------
 
 fn main() {
+    // `--load FILE` solves a building saved by `--save`, or written by hand,
+    // instead of the two parts.
+    if let Some(path) = flag_value("--load") {
+        let building = load_building(&path).unwrap_or_else(|err| panic!("Unable to load {}: {}", path, err));
+        println!("Loaded building:\n{}", &building);
+        match solve_astar(&building, MAX_DEPTH) {
+            (Some(solution), stats) => {
+                println!("A* {}", stats);
+                println!("Steps = {}", solution.depth);
+                print_moves(&building, &solution.moves());
+            }
+            (None, _) => println!("No path found within {} steps", MAX_DEPTH),
+        }
+        return;
+    }
+
     // Part 1
     let mut input_string = String::new();
     let mut file = File::open("input.txt").unwrap();
     let _ = file.read_to_string(&mut input_string);
 
-    let building = read_input(&input_string);
+    let building = read_input(&input_string).unwrap_or_else(|err| panic!("Unable to read input.txt: {}", err));
 
     println!("Input building:\n{}", &building);
 
+    // `--study` shows how the number of steps grows with the number of floors
+    // and the elevator capacity, instead of solving the two parts.
+    if std::env::args().any(|arg| arg == "--study") {
+        study(&building);
+        return;
+    }
+
     println!("Searching for solution...");
-    let steps = process_bfs(&building, MAX_DEPTH);
+    let (solution, stats) = solve_astar(&building, MAX_DEPTH);
+    println!("A* {}", stats);
+    let steps = solution.as_ref().map_or(NO_PATH, |solution| solution.depth);
 
-    if steps < NO_PATH {
+    // The other searches, for comparison.
+    assert!(process_bfs(&building, MAX_DEPTH) == steps);
+    compare_searches(&building, steps);
+
+    if let Some(solution) = solution {
         println!("Part 1: steps = {:?}", steps);
         assert!(steps == 47);
+
+        let moves = solution.moves();
+        moves::replay(&building, &moves).expect("The solution replays");
+        // `--moves` prints every trip and the building after it.
+        if std::env::args().any(|arg| arg == "--moves") {
+            print_moves(&building, &moves);
+        }
+        // `--save DIR` writes every state on the way to DIR, to be loaded again.
+        if let Some(dir) = flag_value("--save") {
+            save_states(&dir, &building, &moves).unwrap_or_else(|err| panic!("Unable to save to {}: {}", dir, err));
+        }
     } else {
         println!("Part 1: no path found within {} steps", MAX_DEPTH);
     }
@@ -344,16 +577,19 @@ fn main() {
     // ----------------------------------------------
     // Part 2
     let mut building2 = building;
-    building2.floors[0].add_chip(Material::Elerium);
-    building2.floors[0].add_gen(Material::Elerium);
-    building2.floors[0].add_chip(Material::Dilithium);
-    building2.floors[0].add_gen(Material::Dilithium);
-
-    let steps2 = process_bfs(&building2, MAX_DEPTH);
-
-    if steps2 < NO_PATH {
-        println!("Part 2: steps = {:?}", steps2);
-        assert!(steps2 > 47);
+    building2.add_chip(0, "elerium");
+    building2.add_gen(0, "elerium");
+    building2.add_chip(0, "dilithium");
+    building2.add_gen(0, "dilithium");
+
+    let (solution2, stats2) = solve_astar(&building2, MAX_DEPTH);
+    println!("A* {}", stats2);
+    compare_searches(&building2, solution2.as_ref().map_or(NO_PATH, |solution| solution.depth));
+
+    if let Some(solution2) = solution2 {
+        println!("Part 2: steps = {:?}", solution2.depth);
+        assert!(solution2.depth > 47);
+        moves::replay(&building2, &solution2.moves()).expect("The solution replays");
     } else {
         println!("Part 2: no path found within {} steps", MAX_DEPTH);
     }
@@ -367,6 +603,7 @@ mod test {
 
     use super::*;
     use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
 
     #[test]
     fn test_bfs() {
@@ -375,7 +612,7 @@ mod test {
                       generator.\nThe third floor contains a lithium generator.\nThe fourth floor \
                       contains nothing relevant.\n";
 
-        let building = read_input(&test_input);
+        let building = read_input(test_input).unwrap();
         assert!(building.is_safe());
 
         //println!("Building:\n{}", &building);
@@ -388,15 +625,201 @@ mod test {
         assert!(steps == 11);
     }
 
+    #[test]
+    fn test_no_floors() {
+        assert!(Building::new(0).unwrap_err() == FormatError::MissingFloor { floor: 0 });
+        assert!(read_input("").unwrap_err() == FormatError::MissingFloor { floor: 0 });
+        let unsafe_input = "The first floor contains a hydrogen-compatible microchip and a lithium generator.";
+        assert!(read_input(unsafe_input).unwrap_err() == FormatError::Unsafe);
+    }
+
+    #[test]
+    fn test_floors_and_capacity() {
+        let mut building = Building::new(6).unwrap();
+        building.add_gen(0, "hydrogen");
+        building.add_chip(0, "hydrogen");
+        assert!(building.distance() == 10);
+        assert!(process_bfs(&building, 20) == 5);
+
+        // Bring it back down from the top.
+        let mut down = Building::new(6).unwrap();
+        down.add_gen(5, "hydrogen");
+        down.add_chip(5, "hydrogen");
+        down.elevator_idx = 5;
+        down.target_floor = 0;
+        assert!(process_bfs(&down, 20) == 5);
+
+        // A middle floor, from either side.
+        let mut middle = building.clone();
+        middle.add_gen(5, "lithium");
+        middle.add_chip(5, "lithium");
+        middle.target_floor = 2;
+        assert!(middle.distance() == 10);
+        assert!(!middle.is_final());
+        assert!(process_bfs(&middle, 30) < NO_PATH);
+
+        // One item at a time can never get a chip and generator moved safely.
+        building.capacity = 1;
+        assert!(process_bfs(&building, 20) == NO_PATH);
+
+        // Room for everything at once.
+        let test_input = "The first floor contains a hydrogen-compatible microchip and a \
+                      lithium-compatible microchip.\nThe second floor contains a hydrogen \
+                      generator.\nThe third floor contains a lithium generator.\nThe fourth floor \
+                      contains nothing relevant.\n";
+        let mut example = read_input(test_input).unwrap();
+        assert!(process_bfs(&example, 20) == 11);
+        example.capacity = 4;
+        assert!(process_bfs(&example, 20) < 11);
+    }
+
+    #[test]
+    fn test_astar() {
+        let test_input = "The first floor contains a hydrogen-compatible microchip and a \
+                      lithium-compatible microchip.\nThe second floor contains a hydrogen \
+                      generator.\nThe third floor contains a lithium generator.\nThe fourth floor \
+                      contains nothing relevant.\n";
+        let mut building = read_input(test_input).unwrap();
+        assert!(building.min_steps() <= 11);
+        assert!(process_astar(&building, 20) == 11);
+
+        for &(num_floors, capacity) in &[(4, 3), (5, 2), (6, 4), (5, 1)] {
+            building.floors.resize(num_floors, BitFloor::new());
+            building.target_floor = num_floors - 1;
+            building.capacity = capacity;
+            assert!(process_astar(&building, 40) == process_bfs(&building, 40));
+        }
+
+        // Nothing left to move.
+        let done = Building::new(4).unwrap();
+        assert!(done.min_steps() == 0);
+        assert!(process_astar(&done, 0) == 0);
+    }
+
+    // Steps found by a plain BFS trying every trip, without merging pairs or
+    // canonical state keys, to check the searches against.
+    pub fn reference_steps(initial: &Building, max_depth: usize) -> usize {
+        let position = |building: &Building| -> (usize, Vec<usize>) {
+            let floors = (0..building.floor_size())
+                .map(|item| building.floors.iter().position(|floor| floor.is_set(item)).unwrap())
+                .collect();
+            (building.elevator_idx, floors)
+        };
+        let mut seen = HashSet::new();
+        seen.insert(position(initial));
+        let mut level = vec![initial.clone()];
+        for depth in 0..max_depth + 1 {
+            let mut next_level = Vec::new();
+            for current in level {
+                if current.is_final() {
+                    return depth;
+                }
+                let items: Vec<usize> = (0..current.floor_size()).filter(|&item| current.item_exists(item)).collect();
+                for size in 1..current.capacity + 1 {
+                    for selection in combinations(&items, size) {
+                        for &direction in &[Direction::Up, Direction::Down] {
+                            if let Some(next) = current.try_move(direction, &selection) {
+                                if seen.insert(position(&next)) {
+                                    next_level.push(next);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            level = next_level;
+        }
+        NO_PATH
+    }
+
+    // Small safe buildings with random layouts, targets and capacities.
+    pub fn random_buildings(count: usize) -> Vec<Building> {
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+        let mut buildings = Vec::new();
+        while buildings.len() < count {
+            let mut building = Building::new(3 + random(2)).unwrap();
+            let num_floors = building.floors.len();
+            for material in &["a", "b", "c"][..1 + random(3)] {
+                building.add_gen(random(num_floors), material);
+                building.add_chip(random(num_floors), material);
+            }
+            building.elevator_idx = random(num_floors);
+            building.target_floor = random(num_floors);
+            building.capacity = 1 + random(3);
+            if building.is_safe() {
+                buildings.push(building);
+            }
+        }
+        buildings
+    }
+
+    #[test]
+    fn test_against_reference() {
+        // Pruned moves used to lose the only solution here.
+        let mut building = Building::new(3).unwrap();
+        building.add_gen(2, "a");
+        building.add_gen(1, "b");
+        building.add_chip(0, "a");
+        building.add_chip(0, "b");
+        building.elevator_idx = 1;
+        assert!(process_bfs(&building, 40) == 13);
+        building.capacity = 3;
+        assert!(process_astar(&building, 40) == 9);
+
+        for building in random_buildings(1000) {
+            let steps = reference_steps(&building, 40);
+            assert!(solve_bfs(&building, 40).0.map_or(NO_PATH, |solution| solution.depth) == steps,
+                    "BFS disagrees on\n{}", building);
+            assert!(solve_astar(&building, 40).0.map_or(NO_PATH, |solution| solution.depth) == steps,
+                    "A* disagrees on\n{}", building);
+        }
+    }
+
+    #[test]
+    fn test_solution_moves() {
+        let test_input = "The first floor contains a hydrogen-compatible microchip and a \
+                      lithium-compatible microchip.\nThe second floor contains a hydrogen \
+                      generator.\nThe third floor contains a lithium generator.\nThe fourth floor \
+                      contains nothing relevant.\n";
+        let building = read_input(test_input).unwrap();
+        for solution in &[solve_bfs(&building, 20).0.unwrap(), solve_astar(&building, 20).0.unwrap()] {
+            let moves = solution.moves();
+            assert!(moves.len() == 11);
+            assert!(moves::replay(&building, &moves).is_ok());
+        }
+
+        let first = building.try_move(Direction::Up, &[1]).unwrap();
+        assert!(first.describe_move(&first.moves()[0]) == "Up with hydrogen microchip");
+        assert!(building.describe_move(&Move { direction: Direction::Down, items: vec![0, 3] }) ==
+                "Down with hydrogen generator and lithium microchip");
+    }
+
+    #[test]
+    fn test_crossing_steps() {
+        assert!(crossing_steps(0, 2) == 0);
+        assert!(crossing_steps(2, 2) == 1);
+        // Up with 2, down with 1, up with 2.
+        assert!(crossing_steps(3, 2) == 3);
+        assert!(crossing_steps(4, 2) == 5);
+        assert!(crossing_steps(7, 3) == 5);
+        assert!(crossing_steps(3, 1) == 3);
+    }
+
     #[test]
     fn test_safe() {
-        let mut building = Building::new();
+        let mut building = Building::new(4).unwrap();
 
-        building.floors[1].add_gen(Material::Hydrogen);
-        building.floors[1].add_chip(Material::Hydrogen);
-        building.floors[1].add_chip(Material::Lithium);
+        building.add_gen(1, "hydrogen");
+        building.add_chip(1, "hydrogen");
+        building.add_chip(1, "lithium");
 
-        building.floors[2].add_gen(Material::Lithium);
+        building.add_gen(2, "lithium");
 
         //println!("Building:\n{}", &building);
 
@@ -407,13 +830,13 @@ mod test {
 
     #[test]
     fn test_not_safe() {
-        let mut building = Building::new();
+        let mut building = Building::new(4).unwrap();
 
-        building.floors[1].add_gen(Material::Hydrogen);
-        building.floors[1].add_chip(Material::Hydrogen);
-        building.floors[0].add_chip(Material::Lithium);
+        building.add_gen(1, "hydrogen");
+        building.add_chip(1, "hydrogen");
+        building.add_chip(0, "lithium");
 
-        building.floors[2].add_gen(Material::Lithium);
+        building.add_gen(2, "lithium");
 
         //println!("Building:\n{}", &building);
 
@@ -424,21 +847,30 @@ mod test {
 
     #[test]
     fn test_hash() {
-        let mut b1 = Building::new();
-        b1.floors[1].add_gen(Material::Hydrogen);
-        b1.floors[1].add_chip(Material::Hydrogen);
-
-        let mut b2 = Building::new();
-        b2.floors[1].add_gen(Material::Lithium);
-        b2.floors[1].add_chip(Material::Lithium);
+        let mut b1 = Building::new(4).unwrap();
+        b1.add_gen(1, "hydrogen");
+        b1.add_chip(1, "hydrogen");
+        b1.add_gen(2, "lithium");
+        b1.add_chip(2, "lithium");
+
+        // The same, with hydrogen and lithium swapped.
+        let mut b2 = Building::new(4).unwrap();
+        b2.add_gen(2, "hydrogen");
+        b2.add_chip(2, "hydrogen");
+        b2.add_gen(1, "lithium");
+        b2.add_chip(1, "lithium");
+        b2.depth = 5;
         //println!("Building:\n{}", &building);
 
-        println!("b1 hash: {:?}", hasher(&b1));
-        println!("b2 hash: {:?}", hasher(&b2));
-        assert!(hasher(&b1) == hasher(&b2));
+        println!("b1 hash: {:?}", hasher(&b1.state_key()));
+        println!("b2 hash: {:?}", hasher(&b2.state_key()));
+        assert!(hasher(&b1.state_key()) == hasher(&b2.state_key()));
+        assert!(b1 == b2);
 
-        b2.floors[0].add_chip(Material::Cobalt);
-        assert!(hasher(&b1) != hasher(&b2));
+        b1.add_chip(0, "cobalt");
+        b2.add_chip(3, "cobalt");
+        assert!(hasher(&b1.state_key()) != hasher(&b2.state_key()));
+        assert!(b1 != b2);
     }
 
     fn hasher<T: Hash>(t: &T) -> u64 {
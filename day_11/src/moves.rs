@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+use Building;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+// One elevator trip, and the items carried on it.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Move {
+    pub direction: Direction,
+    pub items: Vec<usize>,
+}
+
+// The moves that led to a search state, newest first. Shared between a state
+// and everything reached from it.
+#[derive(Debug)]
+pub struct Trail {
+    pub step: Move,
+    pub prev: Option<Rc<Trail>>,
+}
+
+// Why a list of moves doesn't solve a building. `step` counts from 0.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum ReplayError {
+    // The elevator can't make the trip: no floor that way, too many or too
+    // few items, or items that aren't on the elevator's floor.
+    IllegalMove { step: usize },
+    // The trip leaves a chip with another material's generator.
+    Unsafe { step: usize },
+    // Every move was fine, but some items aren't on the target floor.
+    NotFinal,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::IllegalMove { step } => write!(f, "move {} can't be made", step),
+            ReplayError::Unsafe { step } => write!(f, "move {} fries a microchip", step),
+            ReplayError::NotFinal => write!(f, "moves end with items off the target floor"),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn description(&self) -> &str {
+        "invalid move list"
+    }
+}
+
+// Make each move in turn from `initial`, checking every state on the way is
+// safe and the last one is final. Returns the final state.
+pub fn replay(initial: &Building, moves: &[Move]) -> Result<Building, ReplayError> {
+    let mut current = initial.clone();
+    for (step, next_move) in moves.iter().enumerate() {
+        current = current.carry(next_move.direction, &next_move.items)
+            .ok_or(ReplayError::IllegalMove { step })?;
+        if !current.is_safe() {
+            return Err(ReplayError::Unsafe { step });
+        }
+    }
+    if current.is_final() {
+        Ok(current)
+    } else {
+        Err(ReplayError::NotFinal)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use read_input;
+
+    const EXAMPLE: &str = "The first floor contains a hydrogen-compatible microchip and a \
+                           lithium-compatible microchip.\nThe second floor contains a hydrogen \
+                           generator.\nThe third floor contains a lithium generator.\nThe fourth \
+                           floor contains nothing relevant.\n";
+
+    fn step(direction: Direction, items: &[usize]) -> Move {
+        Move { direction, items: items.to_vec() }
+    }
+
+    #[test]
+    fn replay_solution() {
+        // Hydrogen is material 0 and lithium 1; chips are read first.
+        let building = read_input(EXAMPLE).unwrap();
+        let moves = vec![step(Direction::Up, &[1]),
+                         step(Direction::Up, &[0, 1]),
+                         step(Direction::Down, &[1]),
+                         step(Direction::Down, &[1]),
+                         step(Direction::Up, &[1, 3]),
+                         step(Direction::Up, &[1, 3]),
+                         step(Direction::Up, &[1, 3]),
+                         step(Direction::Down, &[1]),
+                         step(Direction::Up, &[0, 2]),
+                         step(Direction::Down, &[3]),
+                         step(Direction::Up, &[1, 3])];
+        let done = replay(&building, &moves).unwrap();
+        assert!(done.depth == 11);
+        assert!(done.moves() == moves);
+
+        assert!(replay(&building, &moves[..10]) == Err(ReplayError::NotFinal));
+    }
+
+    #[test]
+    fn replay_errors() {
+        let building = read_input(EXAMPLE).unwrap();
+        // No floor below the first.
+        assert!(replay(&building, &[step(Direction::Down, &[1])]) ==
+                Err(ReplayError::IllegalMove { step: 0 }));
+        // The hydrogen generator isn't on the first floor.
+        assert!(replay(&building, &[step(Direction::Up, &[0])]) ==
+                Err(ReplayError::IllegalMove { step: 0 }));
+        // Too many for the elevator.
+        assert!(replay(&building, &[step(Direction::Up, &[1, 3, 5])]) ==
+                Err(ReplayError::IllegalMove { step: 0 }));
+        // The lithium chip meets the hydrogen generator on the second floor.
+        assert!(replay(&building, &[step(Direction::Up, &[3])]) ==
+                Err(ReplayError::Unsafe { step: 0 }));
+        assert!(ReplayError::Unsafe { step: 0 }.to_string() == "move 0 fries a microchip");
+    }
+}
@@ -0,0 +1,265 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+
+use state_key::StateKey;
+use {next_states, predecessors, Building, NO_PATH};
+
+// What a search cost. Memory is counted in search states, as they're what
+// the visited sets and queues hold.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct SearchStats {
+    // States expanded, counting a state again each time it's expanded.
+    pub visited: usize,
+    // Most states held at once.
+    pub peak_states: usize,
+}
+
+impl SearchStats {
+    pub fn new() -> SearchStats {
+        SearchStats { visited: 0, peak_states: 0 }
+    }
+
+    // Note that `states` are held right now.
+    pub fn hold(&mut self, states: usize) {
+        self.peak_states = cmp::max(self.peak_states, states);
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "visited {} nodes, holding at most {} states", self.visited, self.peak_states)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// The only state a solution can end in: everything on the target floor,
+// where the last trip left the elevator.
+fn goal_state(initial: &Building) -> Building {
+    let mut goal = initial.clone();
+    let target = goal.target_floor;
+    for n in 0..goal.floors.len() {
+        for item in 0..goal.floor_size() {
+            if goal.floors[n].is_set(item) {
+                goal.floors[n].clear(item);
+                goal.floors[target].set(item);
+            }
+        }
+    }
+    goal.elevator_idx = target;
+    goal.depth = 0;
+    goal.trail = None;
+    goal
+}
+
+// Breadth-first search from the start and back from the goal at the same
+// time, a level at a time from whichever side is smaller, until they meet.
+// Returns the number of steps (NO_PATH on failure).
+pub fn solve_bidirectional(initial: &Building, max_depth: usize) -> (usize, SearchStats) {
+    let mut stats = SearchStats::new();
+    if initial.is_final() {
+        return (0, stats);
+    }
+
+    let goal = goal_state(initial);
+
+    // Fewest steps from the start, and to the goal, for each state seen.
+    let mut forward: HashMap<StateKey, usize> = HashMap::new();
+    let mut backward: HashMap<StateKey, usize> = HashMap::new();
+    forward.insert(initial.state_key(), 0);
+    backward.insert(goal.state_key(), 0);
+
+    let mut forward_frontier = vec![initial.clone()];
+    let mut backward_frontier = vec![goal];
+    let (mut forward_depth, mut backward_depth) = (0, 0);
+
+    while !forward_frontier.is_empty() && !backward_frontier.is_empty() &&
+          forward_depth + backward_depth < max_depth {
+        let grow_forward = forward_frontier.len() <= backward_frontier.len();
+        let (frontier, seen, other_seen) = if grow_forward {
+            forward_depth += 1;
+            (&mut forward_frontier, &mut forward, &backward)
+        } else {
+            backward_depth += 1;
+            (&mut backward_frontier, &mut backward, &forward)
+        };
+
+        // Finish the level, as a later state in it may meet the other side sooner.
+        let mut best_path = NO_PATH;
+        let mut next_frontier = Vec::new();
+        for current in frontier.drain(..) {
+            stats.visited += 1;
+            let neighbours = if grow_forward { next_states(&current) } else { predecessors(&current) };
+            for next in neighbours {
+                let key = next.state_key();
+                if let Some(&other_depth) = other_seen.get(&key) {
+                    best_path = cmp::min(best_path, next.depth + other_depth);
+                }
+                if let Entry::Vacant(entry) = seen.entry(key) {
+                    entry.insert(next.depth);
+                    next_frontier.push(next);
+                }
+            }
+        }
+        *frontier = next_frontier;
+        stats.hold(forward.len() + backward.len());
+
+        if best_path < NO_PATH {
+            return (if best_path <= max_depth { best_path } else { NO_PATH }, stats);
+        }
+    }
+
+    (NO_PATH, stats)
+}
+
+// -----------------------------------------------------------------------------
+
+// Most states IDA* remembers the depth of in one pass.
+const IDA_TABLE_SIZE: usize = 1 << 16;
+
+// Iterative deepening A*: depth-first searches cut off where the lower bound
+// on total steps passes a limit, raising the limit each time. Each pass
+// remembers the shallowest depth it reached states at, up to IDA_TABLE_SIZE
+// of them, so memory stays bounded whatever the building.
+// Returns the number of steps (NO_PATH on failure).
+pub fn solve_ida(initial: &Building, max_depth: usize) -> (usize, SearchStats) {
+    let mut stats = SearchStats::new();
+    let mut bound = initial.min_steps();
+    while bound <= max_depth {
+        let mut table = HashMap::new();
+        table.insert(initial.state_key(), 0);
+        let mut path = vec![initial.state_key()];
+        match ida_search(initial, bound, &mut path, &mut table, &mut stats) {
+            Ok(steps) => return (steps, stats),
+            Err(next_bound) => bound = next_bound,
+        }
+    }
+    (NO_PATH, stats)
+}
+
+// Search below `current` for a solution within `bound` steps. Returns the
+// steps if one is found, or else the lowest bound that would reach further.
+fn ida_search(current: &Building,
+              bound: usize,
+              path: &mut Vec<StateKey>,
+              table: &mut HashMap<StateKey, usize>,
+              stats: &mut SearchStats)
+              -> Result<usize, usize> {
+    let estimate = current.depth + current.min_steps();
+    if estimate > bound {
+        return Err(estimate);
+    }
+    if current.is_final() {
+        return Ok(current.depth);
+    }
+
+    stats.visited += 1;
+    let mut next_bound = NO_PATH;
+    for next in next_states(current) {
+        // Don't go round in circles, or search below a state again where an
+        // earlier search of it had as many steps to spare.
+        let key = next.state_key();
+        if path.contains(&key) || table.get(&key).is_some_and(|&depth| depth <= next.depth) {
+            continue;
+        }
+        if table.len() < IDA_TABLE_SIZE || table.contains_key(&key) {
+            table.insert(key.clone(), next.depth);
+        }
+
+        path.push(key);
+        stats.hold(path.len() + table.len());
+        let result = ida_search(&next, bound, path, table, stats);
+        path.pop();
+
+        match result {
+            Ok(steps) => return Ok(steps),
+            Err(next_estimate) => next_bound = cmp::min(next_bound, next_estimate),
+        }
+    }
+    Err(next_bound)
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test::{random_buildings, reference_steps};
+    use {process_bfs, read_input, BitFloor};
+
+    const EXAMPLE: &str = "The first floor contains a hydrogen-compatible microchip and a \
+                           lithium-compatible microchip.\nThe second floor contains a hydrogen \
+                           generator.\nThe third floor contains a lithium generator.\nThe fourth \
+                           floor contains nothing relevant.\n";
+
+    #[test]
+    fn same_steps_as_bfs() {
+        let mut building = read_input(EXAMPLE).unwrap();
+        assert!(solve_bidirectional(&building, 20).0 == 11);
+        assert!(solve_ida(&building, 20).0 == 11);
+
+        for &(num_floors, capacity) in &[(4, 3), (5, 2), (6, 4)] {
+            building.floors.resize(num_floors, BitFloor::new());
+            building.target_floor = num_floors - 1;
+            building.capacity = capacity;
+            let steps = process_bfs(&building, 40);
+            assert!(solve_bidirectional(&building, 40).0 == steps);
+            assert!(solve_ida(&building, 40).0 == steps);
+        }
+    }
+
+    #[test]
+    fn random_buildings_agree() {
+        // The bidirectional example that used to disagree: 7 steps, not 9.
+        let mut building = Building::new(4).unwrap();
+        for (i, &chip_floor) in [2, 3, 0].iter().enumerate() {
+            let name = format!("m{}", i);
+            building.add_gen(0, &name);
+            building.add_chip(chip_floor, &name);
+        }
+        building.target_floor = 1;
+        building.capacity = 3;
+        assert!(solve_bidirectional(&building, 40).0 == 7);
+
+        for building in random_buildings(300) {
+            // Going back a trip and forward again gets back to the same state.
+            for previous in predecessors(&building) {
+                assert!(next_states(&previous).contains(&building));
+            }
+
+            let steps = reference_steps(&building, 40);
+            assert!(solve_bidirectional(&building, 40).0 == steps,
+                    "Bidirectional BFS disagrees on\n{}", building);
+            assert!(solve_ida(&building, 40).0 == steps, "IDA* disagrees on\n{}", building);
+        }
+    }
+
+    #[test]
+    fn no_path() {
+        let mut building = read_input(EXAMPLE).unwrap();
+        assert!(solve_bidirectional(&building, 10).0 == NO_PATH);
+        assert!(solve_ida(&building, 10).0 == NO_PATH);
+
+        // One item at a time can't keep the chips safe.
+        building.capacity = 1;
+        assert!(solve_bidirectional(&building, 40).0 == NO_PATH);
+        assert!(solve_ida(&building, 40).0 == NO_PATH);
+
+        let done = goal_state(&building);
+        assert!(solve_bidirectional(&done, 0).0 == 0);
+        assert!(solve_ida(&done, 0).0 == 0);
+    }
+
+    #[test]
+    fn stats() {
+        let building = read_input(EXAMPLE).unwrap();
+        let (_, bidirectional) = solve_bidirectional(&building, 20);
+        let (_, ida) = solve_ida(&building, 20);
+        assert!(bidirectional.visited > 0 && bidirectional.peak_states > 0);
+        // IDA* holds the path it's on, and a table no bigger than IDA_TABLE_SIZE.
+        assert!(ida.peak_states > 11 && ida.peak_states <= 12 + IDA_TABLE_SIZE);
+        assert!(ida.to_string().starts_with("visited "));
+    }
+}
@@ -0,0 +1,123 @@
+use Building;
+
+// Canonical form of a search state: the elevator floor, and the (generator
+// floor, chip floor) of every material in sorted order. Materials can be
+// swapped without changing how a state plays out, so states that only
+// differ by a swap share a key. A missing generator or chip is given the
+// floor number one past the top floor.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub enum StateKey {
+    // The floors packed into one integer, elevator in the highest bits.
+    Packed(u128),
+    // The same thing unpacked, for buildings with too many pairs to fit.
+    Wide(usize, Vec<(usize, usize)>),
+}
+
+impl Building {
+    // (generator floor, chip floor) for each material, sorted.
+    fn sorted_pairs(&self) -> Vec<(usize, usize)> {
+        let absent = self.floors.len();
+        let mut pairs = vec![(absent, absent); self.materials.len()];
+        for (n, floor) in self.floors.iter().enumerate() {
+            for (material, pair) in pairs.iter_mut().enumerate() {
+                if floor.is_set(material * 2) {
+                    pair.0 = n;
+                }
+                if floor.is_set(material * 2 + 1) {
+                    pair.1 = n;
+                }
+            }
+        }
+        pairs.sort();
+        pairs
+    }
+
+    pub fn state_key(&self) -> StateKey {
+        let pairs = self.sorted_pairs();
+
+        // Bits for one floor number, or the absent marker.
+        let floor_bits = (usize::BITS - self.floors.len().leading_zeros()) as usize;
+        if floor_bits * (1 + 2 * pairs.len()) > 128 {
+            return StateKey::Wide(self.elevator_idx, pairs);
+        }
+
+        let mut key = self.elevator_idx as u128;
+        for (gen_floor, chip_floor) in pairs {
+            key = (key << floor_bits) | gen_floor as u128;
+            key = (key << floor_bits) | chip_floor as u128;
+        }
+        StateKey::Packed(key)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packed_key() {
+        let mut building = Building::new(4).unwrap();
+        building.add_gen(2, "hydrogen");
+        building.add_chip(0, "hydrogen");
+        building.add_gen(1, "lithium");
+        building.add_chip(1, "lithium");
+        building.elevator_idx = 3;
+        // Elevator 3, then (1, 1) and (2, 0), three bits each.
+        assert!(building.state_key() == StateKey::Packed(0b011_001_001_010_000));
+
+        // The same with the materials swapped.
+        let mut swapped = Building::new(4).unwrap();
+        swapped.add_gen(1, "hydrogen");
+        swapped.add_chip(1, "hydrogen");
+        swapped.add_gen(2, "lithium");
+        swapped.add_chip(0, "lithium");
+        swapped.elevator_idx = 3;
+        assert!(swapped.state_key() == building.state_key());
+
+        swapped.elevator_idx = 2;
+        assert!(swapped.state_key() != building.state_key());
+    }
+
+    #[test]
+    fn wide_key() {
+        // 32 pairs on 4 floors need 195 bits.
+        let mut building = Building::new(4).unwrap();
+        for i in 0..32 {
+            let name = format!("m{}", i);
+            building.add_gen(i % 4, &name);
+            building.add_chip(3, &name);
+        }
+        match building.state_key() {
+            StateKey::Wide(0, ref pairs) => {
+                assert!(pairs.len() == 32);
+                assert!(pairs[0] == (0, 3) && pairs[31] == (3, 3));
+            }
+            _ => panic!("Expected a wide key"),
+        }
+
+        let mut other = building.clone();
+        other.floors[3].clear(1);
+        other.floors[2].set(1);
+        assert!(other.state_key() != building.state_key());
+    }
+
+    #[test]
+    fn unpaired_materials() {
+        // A missing item doesn't look like one on the ground floor.
+        let mut first = Building::new(3).unwrap();
+        first.add_chip(1, "a");
+        first.add_gen(0, "b");
+        first.add_chip(2, "b");
+        let mut second = Building::new(3).unwrap();
+        second.add_chip(2, "a");
+        second.add_gen(0, "b");
+        second.add_chip(1, "b");
+        assert!(first.state_key() != second.state_key());
+
+        let mut paired = first.clone();
+        paired.add_gen(0, "a");
+        assert!(paired.state_key() != first.state_key());
+    }
+}
@@ -0,0 +1,283 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use pattern;
+pub use pattern::{Evidence, PatternMatch};
+
+const OPEN: u8 = b'[';
+const CLOSE: u8 = b']';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Supernet, // outside brackets
+    Hypernet, // inside [...]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // '[' found while already inside a hypernet sequence.
+    NestedBracket { pos: usize },
+    // ']' found without a matching '['.
+    UnmatchedClose { pos: usize },
+    // '[' that is never closed.
+    Unclosed { pos: usize },
+    // A byte outside ASCII; patterns are matched a byte at a time.
+    NonAscii { pos: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::NestedBracket { pos } => write!(f, "nested '[' at byte {}", pos),
+            ParseError::UnmatchedClose { pos } => write!(f, "unmatched ']' at byte {}", pos),
+            ParseError::Unclosed { pos } => write!(f, "unclosed '[' at byte {}", pos),
+            ParseError::NonAscii { pos } => write!(f, "non-ASCII character at byte {}", pos),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        "invalid IPv7 address"
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlsExplanation {
+    // First supernet ABBA found.
+    Supported(PatternMatch),
+    // An ABBA inside a hypernet, which rules out TLS.
+    HypernetAbba(PatternMatch),
+    // No ABBA anywhere in the address.
+    NoAbba,
+}
+
+impl fmt::Display for TlsExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsExplanation::Supported(ref abba) => write!(f, "supports TLS: ABBA {}", abba),
+            TlsExplanation::HypernetAbba(ref abba) => {
+                write!(f, "no TLS: hypernet contains ABBA {}", abba)
+            }
+            TlsExplanation::NoAbba => write!(f, "no TLS: no ABBA in any supernet"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SslExplanation {
+    // A supernet ABA and the hypernet BAB that corresponds to it.
+    Supported { aba: PatternMatch, bab: PatternMatch },
+    NoMatch,
+}
+
+impl fmt::Display for SslExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SslExplanation::Supported { ref aba, ref bab } => {
+                write!(f, "supports SSL: ABA {} matches BAB {}", aba, bab)
+            }
+            SslExplanation::NoMatch => write!(f, "no SSL: no ABA with a matching BAB"),
+        }
+    }
+}
+
+// An address split into alternating supernet and hypernet segments.
+// Even segment indices are always supernets and odd indices hypernets, so a
+// leading or trailing hypernet produces an empty supernet next to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv7Address {
+    segments: Vec<String>,
+}
+
+impl Ipv7Address {
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    pub fn kind(index: usize) -> SegmentKind {
+        if index & 1 == 0 {
+            SegmentKind::Supernet
+        } else {
+            SegmentKind::Hypernet
+        }
+    }
+
+    // TLS: an ABBA in some supernet, and no ABBA in any hypernet.
+    pub fn supports_tls(&self) -> bool {
+        pattern::tls().supports(self)
+    }
+
+    // SSL: an ABA in some supernet with the matching BAB in some hypernet.
+    pub fn supports_ssl(&self) -> bool {
+        pattern::ssl().supports(self)
+    }
+
+    pub fn explain_tls(&self) -> TlsExplanation {
+        // tls() checks the hypernet rule first, so a hypernet ABBA is reported
+        // even when a supernet ABBA also exists.
+        match pattern::tls().check(self) {
+            Ok(evidence) => {
+                match evidence[1] {
+                    Evidence::Single(ref abba) => TlsExplanation::Supported(abba.clone()),
+                    _ => unreachable!("Require rule always yields a single match"),
+                }
+            }
+            Err((_, Evidence::Single(abba))) => TlsExplanation::HypernetAbba(abba),
+            Err(_) => TlsExplanation::NoAbba,
+        }
+    }
+
+    pub fn explain_ssl(&self) -> SslExplanation {
+        match pattern::ssl().check(self) {
+            Ok(mut evidence) => {
+                match evidence.remove(0) {
+                    Evidence::Pair(aba, bab) => SslExplanation::Supported { aba, bab },
+                    _ => unreachable!("Pair rule always yields a pair"),
+                }
+            }
+            Err(_) => SslExplanation::NoMatch,
+        }
+    }
+}
+
+impl FromStr for Ipv7Address {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Ipv7Address, ParseError> {
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let mut open_pos = None;
+
+        for (i, &byte) in input.as_bytes().iter().enumerate() {
+            match (byte, open_pos) {
+                (OPEN, None) => {
+                    segments.push(input[start..i].to_string());
+                    start = i + 1;
+                    open_pos = Some(i);
+                }
+                (OPEN, Some(_)) => return Err(ParseError::NestedBracket { pos: i }),
+                (CLOSE, Some(_)) => {
+                    segments.push(input[start..i].to_string());
+                    start = i + 1;
+                    open_pos = None;
+                }
+                (CLOSE, None) => return Err(ParseError::UnmatchedClose { pos: i }),
+                _ if !byte.is_ascii() => return Err(ParseError::NonAscii { pos: i }),
+                _ => {}
+            }
+        }
+
+        if let Some(pos) = open_pos {
+            return Err(ParseError::Unclosed { pos });
+        }
+        segments.push(input[start..].to_string());
+
+        Ok(Ipv7Address { segments })
+    }
+}
+
+impl fmt::Display for Ipv7Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            match Ipv7Address::kind(i) {
+                SegmentKind::Supernet => write!(f, "{}", segment)?,
+                SegmentKind::Hypernet => write!(f, "[{}]", segment)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_segments() {
+        let addr: Ipv7Address = "abba[mnop]qrst".parse().unwrap();
+        assert_eq!(addr.segments(), &["abba", "mnop", "qrst"]);
+        assert_eq!(addr.to_string(), "abba[mnop]qrst");
+
+        let addr: Ipv7Address = "[ab]cd[ef]".parse().unwrap();
+        assert_eq!(addr.segments(), &["", "ab", "cd", "ef", ""]);
+        assert_eq!(addr.to_string(), "[ab]cd[ef]");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!("ab[c[d]]e".parse::<Ipv7Address>(),
+                   Err(ParseError::NestedBracket { pos: 4 }));
+        assert_eq!("ab]cd".parse::<Ipv7Address>(),
+                   Err(ParseError::UnmatchedClose { pos: 2 }));
+        assert_eq!("ab[cd".parse::<Ipv7Address>(),
+                   Err(ParseError::Unclosed { pos: 2 }));
+        assert_eq!("éé".parse::<Ipv7Address>(),
+                   Err(ParseError::NonAscii { pos: 0 }));
+        assert_eq!("ab[cé]".parse::<Ipv7Address>(),
+                   Err(ParseError::NonAscii { pos: 4 }));
+    }
+
+    #[test]
+    fn short_inputs() {
+        let short = ["", "a", "ab", "[ab]", "x[ab]y", "[]"];
+        for input in short.iter() {
+            let addr: Ipv7Address = input.parse().unwrap();
+            assert!(!addr.supports_tls());
+            assert!(!addr.supports_ssl());
+        }
+    }
+
+    #[test]
+    fn abba_across_brackets_ignored() {
+        // "ab[ba" must not count as an ABBA.
+        let addr: Ipv7Address = "xab[bax]y".parse().unwrap();
+        assert!(!addr.supports_tls());
+    }
+
+    #[test]
+    fn explain_tls() {
+        let addr: Ipv7Address = "abba[mnop]qrst".parse().unwrap();
+        assert_eq!(addr.explain_tls(),
+                   TlsExplanation::Supported(PatternMatch {
+                       segment: 0,
+                       offset: 0,
+                       text: "abba".to_string(),
+                   }));
+
+        let addr: Ipv7Address = "abcd[bddb]xyyx".parse().unwrap();
+        assert_eq!(addr.explain_tls(),
+                   TlsExplanation::HypernetAbba(PatternMatch {
+                       segment: 1,
+                       offset: 0,
+                       text: "bddb".to_string(),
+                   }));
+
+        let addr: Ipv7Address = "aaaa[qwer]tyui".parse().unwrap();
+        assert_eq!(addr.explain_tls(), TlsExplanation::NoAbba);
+    }
+
+    #[test]
+    fn explain_ssl() {
+        let addr: Ipv7Address = "zazbz[bzb]cdb".parse().unwrap();
+        assert_eq!(addr.explain_ssl(),
+                   SslExplanation::Supported {
+                       aba: PatternMatch {
+                           segment: 0,
+                           offset: 2,
+                           text: "zbz".to_string(),
+                       },
+                       bab: PatternMatch {
+                           segment: 1,
+                           offset: 0,
+                           text: "bzb".to_string(),
+                       },
+                   });
+
+        let addr: Ipv7Address = "xyx[xyx]xyx".parse().unwrap();
+        assert_eq!(addr.explain_ssl(), SslExplanation::NoMatch);
+    }
+}
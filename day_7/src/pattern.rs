@@ -0,0 +1,257 @@
+use std::fmt;
+
+use ipv7::{Ipv7Address, SegmentKind};
+
+// A window of `width` bytes that reads the same forwards and backwards.
+// ABBA is Palindrome::new(4) and ABA is Palindrome::new(3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palindrome {
+    width: usize,
+    // Reject windows made of a single repeated character ("aaaa").
+    not_uniform: bool,
+}
+
+impl Palindrome {
+    pub fn new(width: usize) -> Palindrome {
+        assert!(width > 0, "Palindrome width must be non-zero");
+        Palindrome {
+            width,
+            not_uniform: true,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn allow_uniform(self) -> Palindrome {
+        Palindrome { not_uniform: false, ..self }
+    }
+
+    pub fn matches(&self, window: &[u8]) -> bool {
+        if window.len() != self.width {
+            return false;
+        }
+        let is_palindrome = window.iter().eq(window.iter().rev());
+        let is_uniform = window.iter().all(|&b| b == window[0]);
+        is_palindrome && !(self.not_uniform && is_uniform)
+    }
+
+    // Every matching window in segments of the given kind, in address order.
+    pub fn find_all<'a>(&self,
+                        addr: &'a Ipv7Address,
+                        kind: SegmentKind)
+                        -> impl Iterator<Item = PatternMatch> + 'a {
+        let pattern = *self;
+        addr.segments()
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| Ipv7Address::kind(i) == kind)
+            .flat_map(move |(i, segment)| {
+                segment.as_bytes()
+                    .windows(pattern.width)
+                    .enumerate()
+                    .filter(move |&(_, w)| pattern.matches(w))
+                    .map(move |(offset, _)| {
+                        PatternMatch {
+                            segment: i,
+                            offset,
+                            text: segment[offset..offset + pattern.width].to_string(),
+                        }
+                    })
+            })
+    }
+}
+
+// How a match in one segment maps to the window required in another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    // The same window, e.g. "xyx" -> "xyx". Only custom protocols in the tests use it.
+    #[cfg(test)]
+    Identity,
+    // Swap the first two characters everywhere, e.g. ABA -> BAB or ABBA -> BAAB.
+    Invert,
+}
+
+impl Relation {
+    pub fn apply(&self, window: &[u8]) -> Vec<u8> {
+        match *self {
+            #[cfg(test)]
+            Relation::Identity => window.to_vec(),
+            Relation::Invert => {
+                if window.len() < 2 {
+                    return window.to_vec();
+                }
+                let (a, b) = (window[0], window[1]);
+                window.iter()
+                    .map(|&c| if c == a {
+                        b
+                    } else if c == b {
+                        a
+                    } else {
+                        c
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// A pattern found in one segment of an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    pub segment: usize, // segment index, see Ipv7Address::kind()
+    pub offset: usize, // byte offset within the segment
+    pub text: String,
+}
+
+impl fmt::Display for PatternMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "'{}' in {:?} segment {} at offset {}",
+               self.text,
+               Ipv7Address::kind(self.segment),
+               self.segment,
+               self.offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    // At least one match in a segment of `kind`.
+    Require { pattern: Palindrome, kind: SegmentKind },
+    // No match in any segment of `kind`.
+    Forbid { pattern: Palindrome, kind: SegmentKind },
+    // A match in a `from` segment whose related window appears in a `to` segment.
+    Pair {
+        pattern: Palindrome,
+        from: SegmentKind,
+        to: SegmentKind,
+        relation: Relation,
+    },
+}
+
+// What a rule found while being checked. On success this is the match that
+// satisfied it; on failure, the match that violated it (if any).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Evidence {
+    Nothing,
+    Single(PatternMatch),
+    Pair(PatternMatch, PatternMatch),
+}
+
+impl Rule {
+    pub fn check(&self, addr: &Ipv7Address) -> Result<Evidence, Evidence> {
+        match *self {
+            Rule::Require { pattern, kind } => {
+                match pattern.find_all(addr, kind).next() {
+                    Some(m) => Ok(Evidence::Single(m)),
+                    None => Err(Evidence::Nothing),
+                }
+            }
+            Rule::Forbid { pattern, kind } => {
+                match pattern.find_all(addr, kind).next() {
+                    Some(m) => Err(Evidence::Single(m)),
+                    None => Ok(Evidence::Nothing),
+                }
+            }
+            Rule::Pair { pattern, from, to, relation } => {
+                let targets: Vec<PatternMatch> = pattern.find_all(addr, to).collect();
+                for source in pattern.find_all(addr, from) {
+                    let wanted = relation.apply(source.text.as_bytes());
+                    if let Some(target) = targets.iter().find(|t| t.text.as_bytes() == &wanted[..]) {
+                        return Ok(Evidence::Pair(source, target.clone()));
+                    }
+                }
+                Err(Evidence::Nothing)
+            }
+        }
+    }
+}
+
+// A set of rules that must all hold for an address to support a protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    pub rules: Vec<Rule>,
+}
+
+impl Protocol {
+    // Evidence for each rule in order, or the index and evidence of the first failed rule.
+    pub fn check(&self, addr: &Ipv7Address) -> Result<Vec<Evidence>, (usize, Evidence)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| rule.check(addr).map_err(|e| (i, e)))
+            .collect()
+    }
+
+    pub fn supports(&self, addr: &Ipv7Address) -> bool {
+        self.check(addr).is_ok()
+    }
+}
+
+// ABBA in no hypernet, and in at least one supernet.
+pub fn tls() -> Protocol {
+    let abba = Palindrome::new(4);
+    Protocol {
+        rules: vec![Rule::Forbid {
+                        pattern: abba,
+                        kind: SegmentKind::Hypernet,
+                    },
+                    Rule::Require {
+                        pattern: abba,
+                        kind: SegmentKind::Supernet,
+                    }],
+    }
+}
+
+// ABA in a supernet with the corresponding BAB in a hypernet.
+pub fn ssl() -> Protocol {
+    Protocol {
+        rules: vec![Rule::Pair {
+                        pattern: Palindrome::new(3),
+                        from: SegmentKind::Supernet,
+                        to: SegmentKind::Hypernet,
+                        relation: Relation::Invert,
+                    }],
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn palindrome_windows() {
+        assert!(Palindrome::new(4).matches(b"abba"));
+        assert!(!Palindrome::new(4).matches(b"aaaa"));
+        assert!(Palindrome::new(4).allow_uniform().matches(b"aaaa"));
+        assert!(Palindrome::new(5).matches(b"abcba"));
+        assert!(Palindrome::new(5).matches(b"aabaa"));
+        assert!(!Palindrome::new(5).matches(b"abcab"));
+        assert!(!Palindrome::new(1).matches(b"a"));
+    }
+
+    #[test]
+    fn relations() {
+        assert_eq!(Relation::Invert.apply(b"aba"), b"bab".to_vec());
+        assert_eq!(Relation::Invert.apply(b"abba"), b"baab".to_vec());
+        assert_eq!(Relation::Identity.apply(b"xyx"), b"xyx".to_vec());
+    }
+
+    #[test]
+    fn custom_protocol() {
+        // A 5-wide palindrome outside brackets, repeated verbatim inside them.
+        let proto = Protocol {
+            rules: vec![Rule::Pair {
+                            pattern: Palindrome::new(5),
+                            from: SegmentKind::Supernet,
+                            to: SegmentKind::Hypernet,
+                            relation: Relation::Identity,
+                        }],
+        };
+        let addr: Ipv7Address = "xabcbay[qqabcbaqq]".parse().unwrap();
+        assert!(proto.supports(&addr));
+        let addr: Ipv7Address = "xabcbay[qqbcacbqq]".parse().unwrap();
+        assert!(!proto.supports(&addr));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use matrix::Matrix2D;
+
+// Output options shared by the PBM and GIF writers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSettings {
+    // Each screen pixel becomes a scale x scale block.
+    pub scale: usize,
+    // Time each GIF frame is shown, in hundredths of a second.
+    pub frame_delay: u16,
+}
+
+impl Default for ExportSettings {
+    fn default() -> ExportSettings {
+        ExportSettings {
+            scale: 4,
+            frame_delay: 10,
+        }
+    }
+}
+
+fn scaled_size(frame: &Matrix2D<bool>, scale: usize) -> (usize, usize) {
+    (frame.cols() * scale, frame.rows() * scale)
+}
+
+fn scaled_pixel(frame: &Matrix2D<bool>, scale: usize, x: usize, y: usize) -> bool {
+    frame[(y / scale, x / scale)]
+}
+
+// -----------------------------------------------------------------------------
+// Netpbm
+
+// Write one frame as a binary (P4) PBM image. Lit pixels are 1, i.e. black.
+pub fn write_pbm<W: Write>(frame: &Matrix2D<bool>, scale: usize, out: &mut W) -> io::Result<()> {
+    let (width, height) = scaled_size(frame, scale);
+    write!(out, "P4\n{} {}\n", width, height)?;
+
+    // Rows are packed MSB-first and padded to a whole byte.
+    let mut row = vec![0u8; width.div_ceil(8)];
+    for y in 0..height {
+        for byte in row.iter_mut() {
+            *byte = 0;
+        }
+        for x in 0..width {
+            if scaled_pixel(frame, scale, x, y) {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        out.write_all(&row)?;
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// GIF
+
+// Palette index 0 is an unlit pixel, 1 is lit. GIF needs an LZW minimum code
+// size of at least 2, so the table is padded out to four entries.
+const PALETTE: [u8; 12] = [0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+const MIN_CODE_SIZE: u8 = 2;
+const MAX_CODE_SIZE: u32 = 12;
+
+// Write every frame into a single looping GIF89a animation.
+pub fn write_gif<W: Write>(frames: &[Matrix2D<bool>],
+                           settings: &ExportSettings,
+                           out: &mut W)
+                           -> io::Result<()> {
+    let (width, height) = match frames.first() {
+        Some(frame) => scaled_size(frame, settings.scale),
+        None => (0, 0),
+    };
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "image too large for GIF"));
+    }
+    let (width, height) = (width as u16, height as u16);
+
+    // Header and logical screen descriptor, with a 4-entry global color table.
+    out.write_all(b"GIF89a")?;
+    out.write_all(&width.to_le_bytes())?;
+    out.write_all(&height.to_le_bytes())?;
+    out.write_all(&[0x91, 0, 0])?;
+    out.write_all(&PALETTE)?;
+
+    // NETSCAPE2.0 extension: loop forever.
+    out.write_all(&[0x21, 0xFF, 0x0B])?;
+    out.write_all(b"NETSCAPE2.0")?;
+    out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+    for frame in frames {
+        if scaled_size(frame, settings.scale) != (width as usize, height as usize) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "all frames must be the same size"));
+        }
+
+        // Graphic control extension, for the frame delay.
+        out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        out.write_all(&settings.frame_delay.to_le_bytes())?;
+        out.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor covering the whole canvas.
+        out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0x00])?;
+
+        let mut indices = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                indices.push(scaled_pixel(frame, settings.scale, x, y) as u8);
+            }
+        }
+
+        out.write_all(&[MIN_CODE_SIZE])?;
+        for block in lzw_encode(&indices).chunks(255) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0x00])?;
+    }
+
+    out.write_all(&[0x3B])
+}
+
+// Packs variable-width codes LSB-first, as GIF expects.
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u32, size: u32) {
+        self.acc |= code << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u32 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut out = BitWriter {
+        bytes: Vec::new(),
+        acc: 0,
+        bits: 0,
+    };
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut code_size = MIN_CODE_SIZE as u32 + 1;
+    let mut next_code = end + 1;
+
+    out.write(clear, code_size);
+
+    let mut iter = indices.iter();
+    let mut prefix = match iter.next() {
+        Some(&first) => first as u32,
+        None => {
+            out.write(end, code_size);
+            return out.finish();
+        }
+    };
+
+    for &pixel in iter {
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+
+        out.write(prefix, code_size);
+        table.insert((prefix, pixel), next_code);
+        next_code += 1;
+
+        // The decoder adds its table entries one code behind us, so widen
+        // one step later than the table size alone would suggest.
+        if next_code > (1 << code_size) && code_size < MAX_CODE_SIZE {
+            code_size += 1;
+        }
+        if next_code == 1 << MAX_CODE_SIZE {
+            out.write(clear, code_size);
+            table.clear();
+            code_size = MIN_CODE_SIZE as u32 + 1;
+            next_code = end + 1;
+        }
+        prefix = pixel as u32;
+    }
+
+    out.write(prefix, code_size);
+    out.write(end, code_size);
+    out.finish()
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Straightforward GIF LZW decoder, for checking the encoder.
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+        table.push(Vec::new());
+        table.push(Vec::new());
+
+        let mut code_size = MIN_CODE_SIZE as usize + 1;
+        let mut pos = 0;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut output = Vec::new();
+
+        loop {
+            let mut code = 0usize;
+            for i in 0..code_size {
+                let bit = (data[(pos + i) / 8] >> ((pos + i) % 8)) & 1;
+                code |= (bit as usize) << i;
+            }
+            pos += code_size;
+
+            if code == clear {
+                table.truncate(end + 1);
+                code_size = MIN_CODE_SIZE as usize + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                return output;
+            }
+
+            let entry = match prev {
+                None => table[code].clone(),
+                Some(ref p) => {
+                    let entry = if code < table.len() {
+                        table[code].clone()
+                    } else {
+                        let mut e = p.clone();
+                        e.push(p[0]);
+                        e
+                    };
+                    let mut new_entry = p.clone();
+                    new_entry.push(entry[0]);
+                    table.push(new_entry);
+                    entry
+                }
+            };
+            if table.len() == 1 << code_size && code_size < MAX_CODE_SIZE as usize {
+                code_size += 1;
+            }
+            output.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let short = vec![0, 1, 1, 0, 0, 0, 1, 1, 1, 1, 0, 1, 0, 1, 0];
+        assert_eq!(lzw_decode(&lzw_encode(&short)), short);
+
+        // Long and noisy enough to fill the table and force a clear code.
+        let mut seed = 12345u32;
+        let noisy: Vec<u8> = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                ((seed >> 16) & 1) as u8
+            })
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&noisy)), noisy);
+
+        assert_eq!(lzw_decode(&lzw_encode(&[])), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn pbm_output() {
+        let frame = Matrix2D::from_rowmajor_vec(2, 3, vec![true, false, true, false, true, false]);
+        let mut out = Vec::new();
+        write_pbm(&frame, 1, &mut out).unwrap();
+        assert_eq!(out, b"P4\n3 2\n\xA0\x40".to_vec());
+
+        let mut out = Vec::new();
+        write_pbm(&frame, 3, &mut out).unwrap();
+        assert_eq!(&out[..7], b"P4\n9 6\n");
+        assert_eq!(&out[7..9], &[0xE3, 0x80]); // ###...### then padding
+    }
+
+    #[test]
+    fn gif_structure() {
+        let frames = vec![Matrix2D::new_with_default(2, 3, false),
+                          Matrix2D::new_with_default(2, 3, true)];
+        let settings = ExportSettings {
+            scale: 2,
+            frame_delay: 25,
+        };
+        let mut out = Vec::new();
+        write_gif(&frames, &settings, &mut out).unwrap();
+
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(&out[6..10], &[6, 0, 4, 0]);
+        assert_eq!(*out.last().unwrap(), 0x3B);
+
+        // First frame: GCE with the delay, then its image data.
+        let gce = out.windows(3).position(|w| w == [0x21, 0xF9, 0x04]).unwrap();
+        assert_eq!(&out[gce + 4..gce + 6], &[25, 0]);
+
+        let image = gce + 8;
+        assert_eq!(out[image], 0x2C);
+        let data_start = image + 10;
+        assert_eq!(out[data_start], MIN_CODE_SIZE);
+        let len = out[data_start + 1] as usize;
+        let data = &out[data_start + 2..data_start + 2 + len];
+        assert_eq!(lzw_decode(data), vec![0u8; 24]);
+    }
+}
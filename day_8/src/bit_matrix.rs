@@ -0,0 +1,347 @@
+use std::fmt;
+use std::ops::Index;
+
+use matrix::{Matrix2D, Region};
+use screen::Screen;
+
+const WORD_BITS: usize = 64;
+
+static LIT: bool = true;
+static UNLIT: bool = false;
+
+// A bit-packed boolean matrix, for displays too large for Matrix2D<bool>.
+// Each row is stored as whole u64 words with column c in bit c % 64 of word
+// c / 64. Padding bits past the last column are always zero.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BitMatrix {
+    rows: usize,
+    cols: usize,
+    words_per_row: usize,
+    data: Vec<u64>,
+}
+
+fn words_for(bits: usize) -> usize {
+    bits.div_ceil(WORD_BITS)
+}
+
+// Mask of the valid bits in the last word of a `bits`-long vector.
+fn last_word_mask(bits: usize) -> u64 {
+    match bits % WORD_BITS {
+        0 => !0,
+        n => (1u64 << n) - 1,
+    }
+}
+
+// Mask of bits [start, end) within a single word, where end <= 64.
+fn bit_range_mask(start: usize, end: usize) -> u64 {
+    let high = if end == WORD_BITS { !0 } else { (1u64 << end) - 1 };
+    high & !((1u64 << start) - 1)
+}
+
+// Move every bit `distance` places towards higher indices. Bits pushed past
+// `bits` are dropped and the low bits are zero-filled.
+fn shift_up(src: &[u64], distance: usize, bits: usize) -> Vec<u64> {
+    let mut dst = vec![0u64; src.len()];
+    let (word_shift, bit_shift) = (distance / WORD_BITS, distance % WORD_BITS);
+    for i in word_shift..src.len() {
+        dst[i] = src[i - word_shift] << bit_shift;
+        if bit_shift != 0 && i > word_shift {
+            dst[i] |= src[i - word_shift - 1] >> (WORD_BITS - bit_shift);
+        }
+    }
+    if let Some(last) = dst.last_mut() {
+        *last &= last_word_mask(bits);
+    }
+    dst
+}
+
+// Move every bit `distance` places towards lower indices.
+fn shift_down(src: &[u64], distance: usize) -> Vec<u64> {
+    let mut dst = vec![0u64; src.len()];
+    let (word_shift, bit_shift) = (distance / WORD_BITS, distance % WORD_BITS);
+    for i in 0..src.len().saturating_sub(word_shift) {
+        dst[i] = src[i + word_shift] >> bit_shift;
+        if bit_shift != 0 && i + word_shift + 1 < src.len() {
+            dst[i] |= src[i + word_shift + 1] << (WORD_BITS - bit_shift);
+        }
+    }
+    dst
+}
+
+// Rotate a `bits`-long vector towards higher indices.
+fn rotate_up(src: &[u64], distance: usize, bits: usize) -> Vec<u64> {
+    if bits == 0 {
+        return src.to_vec();
+    }
+    let distance = distance % bits;
+    if distance == 0 {
+        return src.to_vec();
+    }
+    let high = shift_up(src, distance, bits);
+    let low = shift_down(src, bits - distance);
+    high.iter().zip(low.iter()).map(|(h, l)| h | l).collect()
+}
+
+impl BitMatrix {
+    pub fn new(rows: usize, cols: usize) -> BitMatrix {
+        let words_per_row = words_for(cols);
+        BitMatrix {
+            rows,
+            cols,
+            words_per_row,
+            data: vec![0u64; rows * words_per_row],
+        }
+    }
+
+    #[allow(dead_code)] // Used in test harness
+    pub fn from_matrix(matrix: &Matrix2D<bool>) -> BitMatrix {
+        let mut bits = BitMatrix::new(matrix.rows(), matrix.cols());
+        for r in 0..matrix.rows() {
+            for c in 0..matrix.cols() {
+                bits.put((r, c), matrix[(r, c)]);
+            }
+        }
+        bits
+    }
+
+    pub fn to_matrix(&self) -> Matrix2D<bool> {
+        let mut matrix = Matrix2D::new_with_default(self.rows, self.cols, false);
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                matrix[(r, c)] = self.get((r, c));
+            }
+        }
+        matrix
+    }
+
+    fn row_words(&self, row: usize) -> &[u64] {
+        &self.data[row * self.words_per_row..(row + 1) * self.words_per_row]
+    }
+
+    fn row_words_mut(&mut self, row: usize) -> &mut [u64] {
+        &mut self.data[row * self.words_per_row..(row + 1) * self.words_per_row]
+    }
+
+    // Apply `f(word, mask)` to every word overlapping columns [start, end) of a row.
+    fn update_range<F: Fn(u64, u64) -> u64>(&mut self, row: usize, start: usize, end: usize, f: F) {
+        let words = self.row_words_mut(row);
+        let mut c = start;
+        while c < end {
+            let word = c / WORD_BITS;
+            let word_end = ((word + 1) * WORD_BITS).min(end);
+            let mask = bit_range_mask(c % WORD_BITS, word_end - word * WORD_BITS);
+            words[word] = f(words[word], mask);
+            c = word_end;
+        }
+    }
+
+    // Copy a column into a packed bit vector, and back.
+    fn gather_col(&self, col: usize) -> Vec<u64> {
+        let mut bits = vec![0u64; words_for(self.rows)];
+        for r in 0..self.rows {
+            if self.get((r, col)) {
+                bits[r / WORD_BITS] |= 1 << (r % WORD_BITS);
+            }
+        }
+        bits
+    }
+
+    fn scatter_col(&mut self, col: usize, bits: &[u64]) {
+        let (word, mask) = (col / WORD_BITS, 1u64 << (col % WORD_BITS));
+        let words_per_row = self.words_per_row;
+        for r in 0..self.rows {
+            let cell = &mut self.data[r * words_per_row + word];
+            if bits[r / WORD_BITS] & (1 << (r % WORD_BITS)) != 0 {
+                *cell |= mask;
+            } else {
+                *cell &= !mask;
+            }
+        }
+    }
+}
+
+impl Screen for BitMatrix {
+    fn blank(rows: usize, cols: usize) -> BitMatrix {
+        BitMatrix::new(rows, cols)
+    }
+
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn get(&self, pos: (usize, usize)) -> bool {
+        assert!(pos.0 < self.rows && pos.1 < self.cols);
+        let word = self.data[pos.0 * self.words_per_row + pos.1 / WORD_BITS];
+        word & (1 << (pos.1 % WORD_BITS)) != 0
+    }
+
+    fn put(&mut self, pos: (usize, usize), value: bool) {
+        assert!(pos.0 < self.rows && pos.1 < self.cols);
+        let word = &mut self.data[pos.0 * self.words_per_row + pos.1 / WORD_BITS];
+        let mask = 1 << (pos.1 % WORD_BITS);
+        if value {
+            *word |= mask;
+        } else {
+            *word &= !mask;
+        }
+    }
+
+    fn fill_region(&mut self, pos: (usize, usize), height: usize, width: usize, value: bool) {
+        // Clipping also keeps the padding bits past the last column zero.
+        let region = Region::new(pos, height, width).clip(self.rows, self.cols);
+        let (start, end) = (region.col, region.col + region.cols);
+        for r in region.row..region.row + region.rows {
+            if value {
+                self.update_range(r, start, end, |word, mask| word | mask);
+            } else {
+                self.update_range(r, start, end, |word, mask| word & !mask);
+            }
+        }
+    }
+
+    fn invert_region(&mut self, pos: (usize, usize), height: usize, width: usize) {
+        let region = Region::new(pos, height, width).clip(self.rows, self.cols);
+        let (start, end) = (region.col, region.col + region.cols);
+        for r in region.row..region.row + region.rows {
+            self.update_range(r, start, end, |word, mask| word ^ mask);
+        }
+    }
+
+    fn rotate_row_right(&mut self, row: usize, distance: usize) {
+        let rotated = rotate_up(self.row_words(row), distance, self.cols);
+        self.row_words_mut(row).copy_from_slice(&rotated);
+    }
+
+    fn rotate_col_down(&mut self, col: usize, distance: usize) {
+        let rotated = rotate_up(&self.gather_col(col), distance, self.rows);
+        self.scatter_col(col, &rotated);
+    }
+
+    fn shift_row_right(&mut self, row: usize, distance: usize) {
+        let shifted = if distance >= self.cols {
+            vec![0u64; self.words_per_row]
+        } else {
+            shift_up(self.row_words(row), distance, self.cols)
+        };
+        self.row_words_mut(row).copy_from_slice(&shifted);
+    }
+
+    fn shift_col_down(&mut self, col: usize, distance: usize) {
+        let shifted = if distance >= self.rows {
+            vec![0u64; words_for(self.rows)]
+        } else {
+            shift_up(&self.gather_col(col), distance, self.rows)
+        };
+        self.scatter_col(col, &shifted);
+    }
+
+    fn count_lit(&self) -> usize {
+        self.data.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+// (row, col) indexing
+impl Index<(usize, usize)> for BitMatrix {
+    type Output = bool;
+    fn index(&self, index: (usize, usize)) -> &bool {
+        if self.get(index) { &LIT } else { &UNLIT }
+    }
+}
+
+impl fmt::Display for BitMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in 0usize..self.rows {
+            for c in 0usize..self.cols {
+                let val = if self[(r, c)] { '#' } else { ' ' };
+                write!(f, "{} ", val)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "")
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use screen_op::ScreenOp;
+
+    // Run the same pseudo-random ops on both backends and compare after each.
+    fn check_against_matrix(rows: usize, cols: usize) {
+        let mut plain = Matrix2D::new_with_default(rows, cols, false);
+        let mut packed = BitMatrix::new(rows, cols);
+
+        let mut seed = 7u64;
+        let mut rand = |limit: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % limit
+        };
+
+        for _ in 0..300 {
+            let (row, col) = (rand(rows), rand(cols));
+            let (height, width) = (rand(rows - row) + 1, rand(cols - col) + 1);
+            let op = match rand(7) {
+                0 => ScreenOp::Rect { col, row, width, height },
+                1 => ScreenOp::Invert { col, row, width, height },
+                2 => ScreenOp::RotateRow { row, by: rand(3 * cols) },
+                3 => ScreenOp::RotateCol { col, by: rand(3 * rows) },
+                4 => ScreenOp::ShiftRow { row, by: rand(cols + 2) },
+                5 => ScreenOp::ShiftCol { col, by: rand(rows + 2) },
+                _ => ScreenOp::Rect { col: 0, row, width: cols, height: 1 },
+            };
+            op.apply(&mut plain);
+            op.apply(&mut packed);
+            assert!(packed.to_matrix() == plain, "Mismatch after {}", op);
+            assert_eq!(packed.count_lit(), plain.count_lit());
+        }
+        assert_eq!(packed.to_string(), plain.to_string());
+        assert!(BitMatrix::from_matrix(&plain) == packed);
+    }
+
+    #[test]
+    fn matches_matrix2d() {
+        check_against_matrix(6, 50);
+        check_against_matrix(3, 7);
+        check_against_matrix(70, 64);
+        check_against_matrix(5, 200);
+        check_against_matrix(130, 1);
+    }
+
+    #[test]
+    fn oversized_regions_match_matrix2d() {
+        // Ops that run past the edge, as unvalidated ops can, are clipped the same way.
+        let ops = [ScreenOp::Rect { col: 3, row: 1, width: 100, height: 2 },
+                   ScreenOp::Invert { col: 60, row: 0, width: 10, height: 9 },
+                   ScreenOp::Rect { col: 0, row: 2, width: 1, height: usize::MAX },
+                   ScreenOp::Invert { col: 0, row: 0, width: usize::MAX, height: 1 },
+                   ScreenOp::Rect { col: 70, row: 5, width: 2, height: 2 }];
+        let mut plain = Matrix2D::new_with_default(4, 65, false);
+        let mut packed = BitMatrix::new(4, 65);
+        for op in ops.iter() {
+            op.apply(&mut plain);
+            op.apply(&mut packed);
+            assert!(packed.to_matrix() == plain, "Mismatch after {}", op);
+            assert_eq!(packed.count_lit(), plain.count_lit());
+        }
+    }
+
+    #[test]
+    fn large_display() {
+        let mut screen = BitMatrix::new(10_000, 10_000);
+        ScreenOp::Rect { col: 0, row: 0, width: 9_999, height: 10_000 }.apply(&mut screen);
+        ScreenOp::RotateRow { row: 5, by: 12_345 }.apply(&mut screen);
+        ScreenOp::RotateCol { col: 9_999, by: 1 }.apply(&mut screen);
+        ScreenOp::Invert { col: 100, row: 100, width: 1, height: 1 }.apply(&mut screen);
+        assert_eq!(screen.count_lit(), 9_999 * 10_000 - 1);
+        assert!(!screen[(5, 9_999)]);
+        assert!(screen[(6, 9_999)]);
+        assert!(!screen[(5, 2_344)]);
+        assert!(!screen[(100, 100)]);
+    }
+}
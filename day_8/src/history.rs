@@ -0,0 +1,328 @@
+use std::error::Error;
+use std::fmt;
+
+use matrix::{Matrix2D, Region};
+use screen_op::ScreenOp;
+
+// An applied op, plus the pixels it overwrote if it can't simply be inverted.
+#[derive(Clone)]
+struct Entry {
+    op: ScreenOp,
+    saved: Option<(Region, Matrix2D<bool>)>,
+}
+
+// A screen with an undo/redo journal of every op applied to it.
+pub struct ScreenHistory {
+    screen: Matrix2D<bool>,
+    entries: Vec<Entry>,
+    // Number of entries currently applied; entries[cursor..] can be redone.
+    cursor: usize,
+}
+
+// The area whose previous pixels are lost when `op` runs.
+fn overwritten_region(op: &ScreenOp, rows: usize, cols: usize) -> Option<Region> {
+    match *op {
+        ScreenOp::Rect { col, row, width, height } => Some(Region::new((row, col), height, width)),
+        ScreenOp::Clear => Some(Region::new((0, 0), rows, cols)),
+        ScreenOp::ShiftRow { row, .. } => Some(Region::new((row, 0), 1, cols)),
+        ScreenOp::ShiftCol { col, .. } => Some(Region::new((0, col), rows, 1)),
+        ScreenOp::Invert { .. } |
+        ScreenOp::RotateRow { .. } |
+        ScreenOp::RotateCol { .. } => None,
+    }
+}
+
+impl ScreenHistory {
+    pub fn new(rows: usize, cols: usize) -> ScreenHistory {
+        ScreenHistory::from_screen(Matrix2D::new_with_default(rows, cols, false))
+    }
+
+    pub fn from_screen(screen: Matrix2D<bool>) -> ScreenHistory {
+        ScreenHistory {
+            screen,
+            entries: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn screen(&self) -> &Matrix2D<bool> {
+        &self.screen
+    }
+
+    // Number of ops currently applied.
+    pub fn step(&self) -> usize {
+        self.cursor
+    }
+
+    // Number of ops in the journal, including any that were undone.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Apply a new op. Anything that was undone can no longer be redone.
+    pub fn apply(&mut self, op: ScreenOp) {
+        self.entries.truncate(self.cursor);
+        let saved = overwritten_region(&op, self.screen.rows(), self.screen.cols())
+            .map(|region| {
+                let view = self.screen.view(region);
+                (view.region(), view.to_matrix())
+            });
+        op.apply(&mut self.screen);
+        self.entries.push(Entry { op, saved });
+        self.cursor += 1;
+    }
+
+    pub fn undo(&mut self) -> Option<ScreenOp> {
+        if self.cursor == 0 {
+            return None;
+        }
+        self.cursor -= 1;
+        let entry = &self.entries[self.cursor];
+        match entry.saved {
+            Some((region, ref pixels)) => {
+                self.screen.blit(&pixels.view(pixels.region()), (region.row, region.col));
+            }
+            None => {
+                let inverse = entry.op.inverse(self.screen.rows(), self.screen.cols());
+                inverse.expect("Op without saved pixels must be invertible")
+                    .apply(&mut self.screen);
+            }
+        }
+        Some(entry.op)
+    }
+
+    pub fn redo(&mut self) -> Option<ScreenOp> {
+        if self.cursor == self.entries.len() {
+            return None;
+        }
+        let op = self.entries[self.cursor].op;
+        op.apply(&mut self.screen);
+        self.cursor += 1;
+        Some(op)
+    }
+
+    // Undo or redo until exactly `step` ops are applied.
+    pub fn replay_to(&mut self, step: usize) {
+        assert!(step <= self.entries.len(),
+                "Can't replay to step {} of {}",
+                step,
+                self.entries.len());
+        while self.cursor > step {
+            self.undo();
+        }
+        while self.cursor < step {
+            self.redo();
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// The screen after op `step` contradicts what the op must have produced,
+// e.g. an unlit pixel inside a rect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildError {
+    pub step: usize,
+    pub op: ScreenOp,
+}
+
+impl fmt::Display for RebuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "screen is inconsistent with step {} ({})",
+               self.step,
+               self.op)
+    }
+}
+
+impl Error for RebuildError {
+    fn description(&self) -> &str {
+        "unable to rebuild initial screen"
+    }
+}
+
+// Work backwards from a final screen to the screen the ops started from.
+// Pixels whose starting value was overwritten come back as None.
+pub fn rebuild_initial(final_screen: &Matrix2D<bool>,
+                       ops: &[ScreenOp])
+                       -> Result<Matrix2D<Option<bool>>, RebuildError> {
+    let (rows, cols) = (final_screen.rows(), final_screen.cols());
+    let mut screen = Matrix2D::new_with_default(rows, cols, None);
+    for r in 0..rows {
+        for c in 0..cols {
+            screen[(r, c)] = Some(final_screen[(r, c)]);
+        }
+    }
+
+    for (step, op) in ops.iter().enumerate().rev() {
+        let error = RebuildError { step, op: *op };
+
+        // Cells the op forced to a known value, which must agree with what we have.
+        let expect = |screen: &Matrix2D<Option<bool>>, pos: (usize, usize), value: bool| {
+            match screen[pos] {
+                Some(v) if v != value => Err(error.clone()),
+                _ => Ok(()),
+            }
+        };
+
+        match *op {
+            ScreenOp::Rect { col, row, width, height } => {
+                for r in row..row + height {
+                    for c in col..col + width {
+                        expect(&screen, (r, c), true)?;
+                        screen[(r, c)] = None;
+                    }
+                }
+            }
+            ScreenOp::Invert { col, row, width, height } => {
+                for r in row..row + height {
+                    for c in col..col + width {
+                        screen[(r, c)] = screen[(r, c)].map(|v| !v);
+                    }
+                }
+            }
+            ScreenOp::Clear => {
+                for r in 0..rows {
+                    for c in 0..cols {
+                        expect(&screen, (r, c), false)?;
+                        screen[(r, c)] = None;
+                    }
+                }
+            }
+            ScreenOp::RotateRow { row, by } => {
+                screen.rotate_row_right(row, (cols - by % cols) % cols)
+            }
+            ScreenOp::RotateCol { col, by } => {
+                screen.rotate_col_down(col, (rows - by % rows) % rows)
+            }
+            ScreenOp::ShiftRow { row, by } => {
+                for c in 0..cols {
+                    if c < by {
+                        expect(&screen, (row, c), false)?;
+                    }
+                    screen[(row, c)] = match c.checked_add(by) {
+                        Some(from) if from < cols => screen[(row, from)],
+                        _ => None,
+                    };
+                }
+            }
+            ScreenOp::ShiftCol { col, by } => {
+                for r in 0..rows {
+                    if r < by {
+                        expect(&screen, (r, col), false)?;
+                    }
+                    screen[(r, col)] = match r.checked_add(by) {
+                        Some(from) if from < rows => screen[(from, col)],
+                        _ => None,
+                    };
+                }
+            }
+        }
+    }
+    Ok(screen)
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use screen_op::parse_program;
+
+    const PROGRAM: &str = "rect 3x2
+rotate column x=1 by 1
+invert 2x2 at 4,1
+rotate row y=0 by 4
+shift row y=1 by 2
+rect 2x1 at 3,2
+shift column x=6 by 1
+clear
+rect 1x1 at 6,2";
+
+    fn snapshots() -> (Vec<ScreenOp>, Vec<Matrix2D<bool>>) {
+        let ops = parse_program(PROGRAM, 3, 7).unwrap();
+        let mut screen = Matrix2D::new_with_default(3, 7, false);
+        let mut frames = vec![screen.clone()];
+        for op in &ops {
+            op.apply(&mut screen);
+            frames.push(screen.clone());
+        }
+        (ops, frames)
+    }
+
+    #[test]
+    fn undo_redo() {
+        let (ops, frames) = snapshots();
+        let mut history = ScreenHistory::new(3, 7);
+        for op in &ops {
+            history.apply(*op);
+        }
+        assert!(*history.screen() == frames[ops.len()]);
+
+        for step in (0..ops.len()).rev() {
+            assert_eq!(history.undo(), Some(ops[step]));
+            assert!(*history.screen() == frames[step]);
+        }
+        assert_eq!(history.undo(), None);
+
+        for step in 0..ops.len() {
+            assert_eq!(history.redo(), Some(ops[step]));
+            assert!(*history.screen() == frames[step + 1]);
+        }
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn replay_and_branch() {
+        let (ops, frames) = snapshots();
+        let mut history = ScreenHistory::new(3, 7);
+        for op in &ops {
+            history.apply(*op);
+        }
+
+        history.replay_to(3);
+        assert!(*history.screen() == frames[3]);
+        history.replay_to(7);
+        assert!(*history.screen() == frames[7]);
+        history.replay_to(2);
+        assert!(*history.screen() == frames[2]);
+
+        // A new op discards the redo tail.
+        history.apply(ScreenOp::Clear);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.redo(), None);
+        history.undo();
+        assert!(*history.screen() == frames[2]);
+    }
+
+    #[test]
+    fn rebuild() {
+        let (ops, frames) = snapshots();
+
+        // Up to step 7 nothing overwrites the untouched pixels, so the blank
+        // start shows through wherever it can be known.
+        let initial = rebuild_initial(&frames[4], &ops[..4]).unwrap();
+        assert!(initial.as_slice().iter().all(|&p| p != Some(true)));
+        assert!(initial[(2, 6)] == Some(false));
+
+        // Lit pixel where a shift must have left a gap.
+        let mut bad = frames[5].clone();
+        bad[(1, 0)] = true;
+        assert_eq!(rebuild_initial(&bad, &ops[..5]).err(),
+                   Some(RebuildError { step: 4, op: ops[4] }));
+
+        // Everything is overwritten by the final clear.
+        let initial = rebuild_initial(&frames[ops.len()], &ops).unwrap();
+        assert!(initial.as_slice().iter().all(|&p| p.is_none()));
+
+        // A shift as big as it gets loses the whole row.
+        let shift = ScreenOp::ShiftRow { row: 1, by: usize::MAX };
+        let initial = rebuild_initial(&Matrix2D::new_with_default(3, 7, false), &[shift]).unwrap();
+        assert!((0..7).all(|c| initial[(1, c)].is_none() && initial[(0, c)] == Some(false)));
+    }
+}
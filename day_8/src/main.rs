@@ -1,65 +1,81 @@
 
-use std::fs::File;
-use std::io::Read;
-
-#[macro_use]
-extern crate lazy_static;
-
-extern crate regex;
-use regex::Regex;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::path::Path;
 
 mod matrix;
 use matrix::Matrix2D;
 
-fn process_instructions(input: &str, rows: usize, cols: usize) -> Matrix2D<bool> {
-    let mut screen = Matrix2D::new_with_default(rows, cols, false);
+mod bit_matrix;
+use bit_matrix::BitMatrix;
+
+mod screen;
+use screen::Screen;
 
-    lazy_static! {
-        static ref REGEX_RECT: Regex
-            = Regex::new("rect ([:digit:]+)x([:digit:]+)").unwrap();
+mod animation;
+use animation::ExportSettings;
 
+mod history;
+use history::ScreenHistory;
 
-        static ref REGEX_ROTATE: Regex
-            = Regex::new("rotate [:alpha:]+ ([xy])=([:digit:]+) by ([:digit:]+)").unwrap();
+mod ocr;
+
+mod screen_op;
+use screen_op::ProgramError;
+
+fn process_instructions<S: Screen>(input: &str,
+                                   rows: usize,
+                                   cols: usize)
+                                   -> Result<S, ProgramError> {
+    let mut screen = S::blank(rows, cols);
+    for op in screen_op::parse_program(input, rows, cols)? {
+        op.apply(&mut screen);
     }
+    Ok(screen)
+}
 
-    for line in input.lines() {
-        if let Some(cap) = REGEX_RECT.captures(line) {
-            let cols: usize = cap.at(1).unwrap().parse().unwrap();
-            let rows: usize = cap.at(2).unwrap().parse().unwrap();
-            // println!("Setting {} rows and {} cols", rows, cols);
-            screen.set((0, 0), rows, cols, true);
-            // println!("{}", screen);
-        } else if let Some(cap) = REGEX_ROTATE.captures(line) {
-            let row_or_col = cap.at(1).unwrap(); // x or y
-            let index = cap.at(2).unwrap().parse::<usize>().unwrap();
-            let amount = cap.at(3).unwrap().parse::<usize>().unwrap();
-            // println!("moving {} at index {} by {}", row_or_col, index, amount);
-            if row_or_col == "x" {
-                // println!("rotating column {} down by {}", index, amount);
-                screen.rotate_col_down(index, amount);
-                // println!("{}", screen);
-            } else {
-                // row
-                // println!("rotating row {} right by {}", index, amount);
-                screen.rotate_row_right(index, amount);
-                // println!("{}", screen);
-            }
-        } else {
-            panic!("Unable to parse input");
-        }
+// Like process_instructions, but keeps a copy of the screen after every step,
+// starting with the blank screen.
+fn record_instructions(input: &str,
+                       rows: usize,
+                       cols: usize)
+                       -> Result<Vec<Matrix2D<bool>>, ProgramError> {
+    let mut screen = Matrix2D::new_with_default(rows, cols, false);
+    let mut frames = vec![screen.clone()];
+    for op in screen_op::parse_program(input, rows, cols)? {
+        op.apply(&mut screen);
+        frames.push(screen.clone());
     }
-    screen
+    Ok(frames)
 }
 
-fn count_lit(screen: Matrix2D<bool>) -> usize {
-    let mut lit = 0usize;
-    for &elm in screen.as_slice() {
-        if elm {
-            lit += 1;
+// Write the recorded frames as <dir>/frame_NNN.pbm and/or a single GIF.
+fn export_frames(frames: &[Matrix2D<bool>],
+                 settings: &ExportSettings,
+                 pbm_dir: Option<&str>,
+                 gif_path: Option<&str>) {
+    if let Some(dir) = pbm_dir {
+        fs::create_dir_all(dir).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            let path = Path::new(dir).join(format!("frame_{:03}.pbm", i));
+            let mut out = BufWriter::new(File::create(path).unwrap());
+            animation::write_pbm(frame, settings.scale, &mut out).unwrap();
         }
+        println!("Wrote {} PBM frames to {}", frames.len(), dir);
+    }
+    if let Some(path) = gif_path {
+        let mut out = BufWriter::new(File::create(path).unwrap());
+        animation::write_gif(frames, settings, &mut out).unwrap();
+        println!("Wrote {} frame animation to {}", frames.len(), path);
     }
-    lit
+}
+
+// Value following `flag` on the command line, if present.
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
 }
 
 fn main() {
@@ -67,14 +83,57 @@ fn main() {
     let mut file = File::open("input.txt").unwrap();
     let _ = file.read_to_string(&mut input_string);
 
-    let screen = process_instructions(&input_string, 6, 50);
+    let screen: Matrix2D<bool> = process_instructions(&input_string, 6, 50)
+        .unwrap_or_else(|e| panic!("{}", e));
     println!("{}", screen);
 
-    let lit = count_lit(screen);
+    let text = ocr::recognize(&screen).unwrap_or_else(|e| panic!("{}", e));
+
+    // The bit-packed screen must agree with the plain one.
+    let packed: BitMatrix = process_instructions(&input_string, 6, 50).unwrap();
+    assert!(packed.to_matrix() == screen);
+
+    let lit = packed.count_lit();
     println!("Part 1: {} pixels lit", lit);
-    assert!(lit = 121);
+    assert!(lit == 121);
+
+    println!("Part 2: screen reads {}", text);
+    assert!(text == "RURUCEOEIL");
 
-    // For part 2, just look at the console rendering of the screen.
+    // Sanity check: working backwards must agree with starting from a blank screen.
+    let ops = screen_op::parse_program(&input_string, 6, 50).unwrap();
+    let initial = history::rebuild_initial(&screen, &ops).unwrap_or_else(|e| panic!("{}", e));
+    assert!(initial.as_slice().iter().all(|&pixel| pixel != Some(true)));
+
+    // And undoing every op must get back to a blank screen, and redoing them
+    // to the same final one.
+    let mut history = ScreenHistory::new(6, 50);
+    for &op in &ops {
+        history.apply(op);
+    }
+    assert!(*history.screen() == screen);
+    history.replay_to(0);
+    assert!(history.screen().as_slice().iter().all(|&pixel| !pixel));
+    let len = history.len();
+    history.replay_to(len);
+    assert!(*history.screen() == screen && history.step() == ops.len());
+
+    // Optional animation export:
+    //   --pbm <dir> --gif <file> [--scale N] [--delay centiseconds]
+    let args: Vec<String> = std::env::args().collect();
+    let pbm_dir = arg_value(&args, "--pbm");
+    let gif_path = arg_value(&args, "--gif");
+    if pbm_dir.is_some() || gif_path.is_some() {
+        let mut settings = ExportSettings::default();
+        if let Some(scale) = arg_value(&args, "--scale") {
+            settings.scale = scale.parse().expect("Invalid --scale");
+        }
+        if let Some(delay) = arg_value(&args, "--delay") {
+            settings.frame_delay = delay.parse().expect("Invalid --delay");
+        }
+        let frames = record_instructions(&input_string, 6, 50).unwrap();
+        export_frames(&frames, &settings, pbm_dir, gif_path);
+    }
 }
 
 #[test]
@@ -82,7 +141,7 @@ fn test() {
     let test_instructions = "rect 3x2\nrotate column x=1 by 1\nrotate row y=0 by 4\nrotate column \
                              x=1 by 1";
 
-    let screen = process_instructions(test_instructions, 3, 7);
+    let screen: Matrix2D<bool> = process_instructions(test_instructions, 3, 7).unwrap();
     println!("{}", screen);
     assert!(screen ==
             Matrix2D::from_rowmajor_vec(3,
@@ -91,3 +150,14 @@ fn test() {
                                              false, true, false, false, false, false, false,
                                              true, false, false, false, false, false]));
 }
+
+#[test]
+fn test_record() {
+    let test_instructions = "rect 3x2\nrotate column x=1 by 1\nrotate row y=0 by 4\nrotate column \
+                             x=1 by 1";
+
+    let frames = record_instructions(test_instructions, 3, 7).unwrap();
+    assert!(frames.len() == 5);
+    assert!(frames[0].count_lit() == 0);
+    assert!(frames[4] == process_instructions(test_instructions, 3, 7).unwrap());
+}
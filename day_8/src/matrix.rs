@@ -2,7 +2,80 @@
 use std::ops::{Index, IndexMut};
 use std::fmt;
 
-#[derive(Eq,PartialEq)]
+// A rectangle of `rows` x `cols` cells with its top-left corner at (row, col).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub row: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl Region {
+    pub fn new(pos: (usize, usize), rows: usize, cols: usize) -> Region {
+        Region {
+            row: pos.0,
+            col: pos.1,
+            rows,
+            cols,
+        }
+    }
+
+    // The part of this region that lies inside a rows x cols matrix.
+    pub fn clip(&self, rows: usize, cols: usize) -> Region {
+        let row = self.row.min(rows);
+        let col = self.col.min(cols);
+        Region {
+            row,
+            col,
+            rows: self.row.saturating_add(self.rows).min(rows) - row,
+            cols: self.col.saturating_add(self.cols).min(cols) - col,
+        }
+    }
+}
+
+// A read-only window onto part of a matrix, indexed relative to its corner.
+pub struct MatrixView<'a, T: 'a + Eq> {
+    matrix: &'a Matrix2D<T>,
+    region: Region,
+}
+
+impl<'a, T: Copy + Eq> MatrixView<'a, T> {
+    pub fn rows(&self) -> usize {
+        self.region.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.region.cols
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn to_matrix(&self) -> Matrix2D<T> {
+        let mut data = Vec::with_capacity(self.rows() * self.cols());
+        for r in 0..self.rows() {
+            for c in 0..self.cols() {
+                data.push(self[(r, c)]);
+            }
+        }
+        Matrix2D::from_rowmajor_vec(self.rows(), self.cols(), data)
+    }
+}
+
+// (row, col) indexing, relative to the view
+impl<'a, T: Eq> Index<(usize, usize)> for MatrixView<'a, T> {
+    type Output = T;
+    fn index(&self, index: (usize, usize)) -> &T {
+        assert!(index.0 < self.region.rows && index.1 < self.region.cols,
+                "View index {:?} out of bounds",
+                index);
+        &self.matrix[(self.region.row + index.0, self.region.col + index.1)]
+    }
+}
+
+#[derive(Clone,Eq,PartialEq)]
 pub struct Matrix2D<T: Eq> {
     rows: usize,
     cols: usize,
@@ -17,18 +90,18 @@ impl<T: Copy + Eq> Matrix2D<T> {
             data.push(default);
         }
         Matrix2D {
-            rows: rows,
-            cols: cols,
-            data: data,
+            rows,
+            cols,
+            data,
         }
     }
 
     #[allow(dead_code)] // Used in test harness
     pub fn from_rowmajor_vec(rows: usize, cols: usize, data: Vec<T>) -> Matrix2D<T> {
         Matrix2D {
-            rows: rows,
-            cols: cols,
-            data: data,
+            rows,
+            cols,
+            data,
         }
     }
 
@@ -50,18 +123,49 @@ impl<T: Copy + Eq> Matrix2D<T> {
         self[a] = tmp;
     }
 
+    // The region covering the whole matrix.
+    pub fn region(&self) -> Region {
+        Region::new((0, 0), self.rows, self.cols)
+    }
+
+    // Set a num_rows x num_cols block with its top-left corner at pos.
     pub fn set(&mut self, pos: (usize, usize), num_rows: usize, num_cols: usize, value: T) {
-        for r in pos.0..num_rows {
-            for c in pos.1..num_cols {
+        self.fill(Region::new(pos, num_rows, num_cols), value);
+    }
+
+    // Set every cell in the region, ignoring any part outside the matrix.
+    pub fn fill(&mut self, region: Region, value: T) {
+        let region = region.clip(self.rows, self.cols);
+        for r in region.row..region.row + region.rows {
+            for c in region.col..region.col + region.cols {
                 self[(r, c)] = value;
             }
         }
     }
 
+    // A view of the region, clipped to the matrix.
+    pub fn view(&self, region: Region) -> MatrixView<'_, T> {
+        MatrixView {
+            matrix: self,
+            region: region.clip(self.rows, self.cols),
+        }
+    }
+
+    // Copy `src` into this matrix with its top-left corner at pos. Cells that
+    // would land outside this matrix are dropped.
+    pub fn blit(&mut self, src: &MatrixView<T>, pos: (usize, usize)) {
+        let dest = Region::new(pos, src.rows(), src.cols()).clip(self.rows, self.cols);
+        for r in 0..dest.rows {
+            for c in 0..dest.cols {
+                self[(dest.row + r, dest.col + c)] = src[(r, c)];
+            }
+        }
+    }
+
     pub fn rotate_col_down(&mut self, col: usize, distance: usize) {
         // Adapted from http://www.cplusplus.com/reference/algorithm/rotate/
         let mut end = (self.rows - 1) as i32;
-        let mut middle = (end - distance as i32) as i32;
+        let mut middle = end - distance as i32;
         let mut next = middle;
         let first = -1i32;
         while end != next {
@@ -79,7 +183,7 @@ impl<T: Copy + Eq> Matrix2D<T> {
     pub fn rotate_row_right(&mut self, row: usize, distance: usize) {
         // Adapted from http://www.cplusplus.com/reference/algorithm/rotate/
         let mut end = (self.cols - 1) as i32;
-        let mut middle = (end - distance as i32) as i32;
+        let mut middle = end - distance as i32;
         let mut next = middle;
         let first = -1i32;
         while end != next {
@@ -93,6 +197,20 @@ impl<T: Copy + Eq> Matrix2D<T> {
             next -= 1;
         }
     }
+
+    // Like rotate_row_right, but pixels pushed past the end are dropped and
+    // the vacated cells are set to `fill`.
+    pub fn shift_row_right(&mut self, row: usize, distance: usize, fill: T) {
+        for c in (0..self.cols).rev() {
+            self[(row, c)] = if c >= distance { self[(row, c - distance)] } else { fill };
+        }
+    }
+
+    pub fn shift_col_down(&mut self, col: usize, distance: usize, fill: T) {
+        for r in (0..self.rows).rev() {
+            self[(r, col)] = if r >= distance { self[(r - distance, col)] } else { fill };
+        }
+    }
 }
 
 // (row, col) indexing
@@ -123,7 +241,7 @@ impl<T: Sized + Eq> IndexMut<(usize, usize)> for Matrix2D<T> {
 //            for c in 0usize..self.cols {
 //                write!(f, "{} ", self[(r, c)])?;
 //            }
-//            write!(f, "\n")?;
+//            writeln!(f)?;
 //        }
 //        write!(f, "")
 //    }
@@ -136,8 +254,71 @@ impl fmt::Display for Matrix2D<bool> {
                 let val = if self[(r, c)] { '#' } else { ' ' };
                 write!(f, "{} ", val)?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         write!(f, "")
     }
 }
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn numbered(rows: usize, cols: usize) -> Matrix2D<usize> {
+        Matrix2D::from_rowmajor_vec(rows, cols, (0..rows * cols).collect())
+    }
+
+    #[test]
+    fn set_with_offset() {
+        let mut m = Matrix2D::new_with_default(4, 5, 0);
+        m.set((1, 2), 2, 3, 1);
+        assert!(m.as_slice() ==
+                [0, 0, 0, 0, 0,
+                  0, 0, 1, 1, 1,
+                  0, 0, 1, 1, 1,
+                  0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fill_clips_to_bounds() {
+        let mut m = Matrix2D::new_with_default(3, 3, 0);
+        m.fill(Region::new((2, 1), 5, 5), 7);
+        assert!(m.as_slice() == [0, 0, 0, 0, 0, 0, 0, 7, 7]);
+
+        // Entirely outside, and overflowing sizes.
+        m.fill(Region::new((5, 5), 1, 1), 9);
+        m.fill(Region::new((1, 1), usize::MAX, usize::MAX), 2);
+        assert!(m.as_slice() == [0, 0, 0, 0, 2, 2, 0, 2, 2]);
+    }
+
+    #[test]
+    fn views() {
+        let m = numbered(4, 5);
+        let view = m.view(Region::new((1, 2), 2, 2));
+        assert_eq!((view.rows(), view.cols()), (2, 2));
+        assert_eq!(view[(0, 0)], 7);
+        assert_eq!(view[(1, 1)], 13);
+        assert!(view.to_matrix() == Matrix2D::from_rowmajor_vec(2, 2, vec![7, 8, 12, 13]));
+
+        let clipped = m.view(Region::new((3, 3), 10, 10));
+        assert_eq!(clipped.region(), Region::new((3, 3), 1, 2));
+        assert_eq!(clipped[(0, 1)], 19);
+    }
+
+    #[test]
+    fn blit_with_offset() {
+        let src = numbered(2, 3);
+        let mut dest = Matrix2D::new_with_default(3, 4, 99);
+        dest.blit(&src.view(src.region()), (1, 2));
+        assert!(dest.as_slice() ==
+                [99, 99, 99, 99,
+                  99, 99, 0, 1,
+                  99, 99, 3, 4]);
+
+        let mut dest = Matrix2D::new_with_default(2, 2, 99);
+        dest.blit(&src.view(Region::new((0, 1), 2, 2)), (0, 0));
+        assert!(dest.as_slice() == [1, 2, 4, 5]);
+    }
+}
@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::fmt;
+
+use matrix::{Matrix2D, Region};
+
+pub const GLYPH_ROWS: usize = 6;
+pub const GLYPH_COLS: usize = 5;
+
+// The standard display font. Each glyph is 6 rows of 5 columns, row-major,
+// with '#' for a lit pixel. Narrow letters leave the last column(s) blank.
+static FONT: &[(char, &str)] = &[
+    ('A', ".##..#..#.#..#.####.#..#.#..#."),
+    ('B', "###..#..#.###..#..#.#..#.###.."),
+    ('C', ".##..#..#.#....#....#..#..##.."),
+    ('E', "####.#....###..#....#....####."),
+    ('F', "####.#....###..#....#....#...."),
+    ('G', ".##..#..#.#....#.##.#..#..###."),
+    ('H', "#..#.#..#.####.#..#.#..#.#..#."),
+    ('I', ".###...#....#....#....#...###."),
+    ('J', "..##....#....#....#.#..#..##.."),
+    ('K', "#..#.#.#..##...#.#..#.#..#..#."),
+    ('L', "#....#....#....#....#....####."),
+    ('O', ".##..#..#.#..#.#..#.#..#..##.."),
+    ('P', "###..#..#.#..#.###..#....#...."),
+    ('R', "###..#..#.#..#.###..#.#..#..#."),
+    ('S', ".###.#....#.....##.....#.###.."),
+    ('U', "#..#.#..#.#..#.#..#.#..#..##.."),
+    ('Y', "#...##...#.#.#...#....#....#.."),
+    ('Z', "####....#...#...#...#....####."),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OcrError {
+    // The screen must be exactly one glyph tall.
+    WrongHeight(usize),
+    // Glyph at `index` (counting from the left) isn't in the font.
+    UnknownGlyph { index: usize, pattern: String },
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OcrError::WrongHeight(rows) => {
+                write!(f, "screen has {} rows, expected {}", rows, GLYPH_ROWS)
+            }
+            OcrError::UnknownGlyph { index, ref pattern } => {
+                writeln!(f, "unknown glyph at position {}:", index)?;
+                for row in pattern.as_bytes().chunks(GLYPH_COLS) {
+                    writeln!(f, "{}", String::from_utf8_lossy(row))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for OcrError {
+    fn description(&self) -> &str {
+        "unable to read screen"
+    }
+}
+
+// Read the screen as text, one character per 5-column cell. Blank cells read
+// as spaces, and a partial cell at the right edge is padded with unlit pixels.
+pub fn recognize(screen: &Matrix2D<bool>) -> Result<String, OcrError> {
+    if screen.rows() != GLYPH_ROWS {
+        return Err(OcrError::WrongHeight(screen.rows()));
+    }
+
+    let num_glyphs = screen.cols().div_ceil(GLYPH_COLS);
+    let mut text = String::with_capacity(num_glyphs);
+    for index in 0..num_glyphs {
+        let pattern = glyph_pattern(screen, index * GLYPH_COLS);
+        if !pattern.contains('#') {
+            text.push(' ');
+            continue;
+        }
+        match FONT.iter().find(|&&(_, glyph)| glyph == pattern) {
+            Some(&(letter, _)) => text.push(letter),
+            None => return Err(OcrError::UnknownGlyph { index, pattern }),
+        }
+    }
+    Ok(text)
+}
+
+fn glyph_pattern(screen: &Matrix2D<bool>, first_col: usize) -> String {
+    // The view is clipped at the right edge; anything past it reads as unlit.
+    let glyph = screen.view(Region::new((0, first_col), GLYPH_ROWS, GLYPH_COLS));
+    let mut pattern = String::with_capacity(GLYPH_ROWS * GLYPH_COLS);
+    for r in 0..GLYPH_ROWS {
+        for c in 0..GLYPH_COLS {
+            let lit = c < glyph.cols() && glyph[(r, c)];
+            pattern.push(if lit { '#' } else { '.' });
+        }
+    }
+    pattern
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn screen_from(rows: &[&str]) -> Matrix2D<bool> {
+        let cols = rows[0].len();
+        let data = rows.iter().flat_map(|row| row.chars().map(|c| c == '#')).collect();
+        Matrix2D::from_rowmajor_vec(rows.len(), cols, data)
+    }
+
+    #[test]
+    fn read_letters() {
+        let screen = screen_from(&["#..#.####.     ",
+                                   "#..#.#....     ",
+                                   "####.###..     ",
+                                   "#..#.#....     ",
+                                   "#..#.#....     ",
+                                   "#..#.####.     "]);
+        assert_eq!(recognize(&screen), Ok("HE ".to_string()));
+    }
+
+    #[test]
+    fn unknown_glyph() {
+        let screen = screen_from(&["#....", ".#...", "..#..", "...#.", "....#", "....."]);
+        match recognize(&screen) {
+            Err(OcrError::UnknownGlyph { index, pattern }) => {
+                assert_eq!(index, 0);
+                assert_eq!(&pattern[..10], "#.....#...");
+            }
+            other => panic!("Expected unknown glyph, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_height() {
+        let screen = Matrix2D::new_with_default(3, 5, false);
+        assert_eq!(recognize(&screen), Err(OcrError::WrongHeight(3)));
+    }
+}
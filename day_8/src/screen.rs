@@ -0,0 +1,81 @@
+use matrix::{Matrix2D, Region};
+
+// The pixel operations ScreenOp::apply needs, so ops can run on either the
+// plain Matrix2D<bool> or the bit-packed BitMatrix.
+pub trait Screen {
+    fn blank(rows: usize, cols: usize) -> Self;
+
+    fn rows(&self) -> usize;
+    fn cols(&self) -> usize;
+
+    fn get(&self, pos: (usize, usize)) -> bool;
+    fn put(&mut self, pos: (usize, usize), value: bool);
+
+    // Set, or flip, every pixel in the height x width region at (row, col).
+    // Any part of the region outside the screen is ignored.
+    fn fill_region(&mut self, pos: (usize, usize), height: usize, width: usize, value: bool);
+    fn invert_region(&mut self, pos: (usize, usize), height: usize, width: usize);
+
+    fn rotate_row_right(&mut self, row: usize, distance: usize);
+    fn rotate_col_down(&mut self, col: usize, distance: usize);
+    // Non-wrapping; vacated pixels are turned off.
+    fn shift_row_right(&mut self, row: usize, distance: usize);
+    fn shift_col_down(&mut self, col: usize, distance: usize);
+
+    fn count_lit(&self) -> usize;
+}
+
+impl Screen for Matrix2D<bool> {
+    fn blank(rows: usize, cols: usize) -> Matrix2D<bool> {
+        Matrix2D::new_with_default(rows, cols, false)
+    }
+
+    fn rows(&self) -> usize {
+        Matrix2D::rows(self)
+    }
+
+    fn cols(&self) -> usize {
+        Matrix2D::cols(self)
+    }
+
+    fn get(&self, pos: (usize, usize)) -> bool {
+        self[pos]
+    }
+
+    fn put(&mut self, pos: (usize, usize), value: bool) {
+        self[pos] = value;
+    }
+
+    fn fill_region(&mut self, pos: (usize, usize), height: usize, width: usize, value: bool) {
+        self.set(pos, height, width, value);
+    }
+
+    fn invert_region(&mut self, pos: (usize, usize), height: usize, width: usize) {
+        let region = Region::new(pos, height, width).clip(self.rows(), self.cols());
+        for r in region.row..region.row + region.rows {
+            for c in region.col..region.col + region.cols {
+                self[(r, c)] = !self[(r, c)];
+            }
+        }
+    }
+
+    fn rotate_row_right(&mut self, row: usize, distance: usize) {
+        Matrix2D::rotate_row_right(self, row, distance);
+    }
+
+    fn rotate_col_down(&mut self, col: usize, distance: usize) {
+        Matrix2D::rotate_col_down(self, col, distance);
+    }
+
+    fn shift_row_right(&mut self, row: usize, distance: usize) {
+        Matrix2D::shift_row_right(self, row, distance, false);
+    }
+
+    fn shift_col_down(&mut self, col: usize, distance: usize) {
+        Matrix2D::shift_col_down(self, col, distance, false);
+    }
+
+    fn count_lit(&self) -> usize {
+        self.as_slice().iter().filter(|&&pixel| pixel).count()
+    }
+}
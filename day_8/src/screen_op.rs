@@ -0,0 +1,342 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(test)]
+use matrix::Matrix2D;
+use screen::Screen;
+
+// A single screen instruction. Regions are given as width x height at a
+// (col, row) offset, matching the "rect AxB" input format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenOp {
+    // rect AxB at X,Y  (plain "rect AxB" is at 0,0)
+    Rect { col: usize, row: usize, width: usize, height: usize },
+    // invert AxB at X,Y
+    Invert { col: usize, row: usize, width: usize, height: usize },
+    // clear
+    Clear,
+    // rotate row y=A by B
+    RotateRow { row: usize, by: usize },
+    // rotate column x=A by B
+    RotateCol { col: usize, by: usize },
+    // shift row y=A by B  (pixels pushed off the right edge are lost)
+    ShiftRow { row: usize, by: usize },
+    // shift column x=A by B  (pixels pushed off the bottom edge are lost)
+    ShiftCol { col: usize, by: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpError {
+    // The line doesn't look like any known instruction.
+    Syntax(String),
+    // A number field couldn't be parsed.
+    BadNumber(String),
+    // An index or region doesn't fit on the screen.
+    OutOfRange { what: &'static str, value: usize, limit: usize },
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpError::Syntax(ref line) => write!(f, "unrecognized instruction '{}'", line),
+            OpError::BadNumber(ref text) => write!(f, "invalid number '{}'", text),
+            OpError::OutOfRange { what, value, limit } => {
+                write!(f, "{} {} out of range for screen size {}", what, value, limit)
+            }
+        }
+    }
+}
+
+impl Error for OpError {
+    fn description(&self) -> &str {
+        "invalid screen instruction"
+    }
+}
+
+// An OpError tagged with the (1-based) input line it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramError {
+    pub line: usize,
+    pub error: OpError,
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl Error for ProgramError {
+    fn description(&self) -> &str {
+        "invalid screen program"
+    }
+}
+
+impl ScreenOp {
+    // Check that every index and region used by this op fits on a rows x cols screen.
+    pub fn validate(&self, rows: usize, cols: usize) -> Result<(), OpError> {
+        // Indices must be < limit; region edges may equal it.
+        let check_index = |what, value, limit| if value >= limit {
+            Err(OpError::OutOfRange { what, value, limit })
+        } else {
+            Ok(())
+        };
+        let check_edge = |what, value, limit| if value > limit {
+            Err(OpError::OutOfRange { what, value, limit })
+        } else {
+            Ok(())
+        };
+        match *self {
+            ScreenOp::Rect { col, row, width, height } |
+            ScreenOp::Invert { col, row, width, height } => {
+                // A size too big to add to its offset can't fit either.
+                let right = col.checked_add(width)
+                    .ok_or(OpError::OutOfRange { what: "width", value: width, limit: cols })?;
+                let bottom = row.checked_add(height)
+                    .ok_or(OpError::OutOfRange { what: "height", value: height, limit: rows })?;
+                check_edge("right edge", right, cols)?;
+                check_edge("bottom edge", bottom, rows)
+            }
+            ScreenOp::Clear => Ok(()),
+            ScreenOp::RotateRow { row, .. } |
+            ScreenOp::ShiftRow { row, .. } => check_index("row", row, rows),
+            ScreenOp::RotateCol { col, .. } |
+            ScreenOp::ShiftCol { col, .. } => check_index("column", col, cols),
+        }
+    }
+
+    // The op that exactly undoes this one on a rows x cols screen, if there is
+    // one. Rect, clear and shifts overwrite pixels and have no inverse.
+    pub fn inverse(&self, rows: usize, cols: usize) -> Option<ScreenOp> {
+        match *self {
+            ScreenOp::Invert { .. } => Some(*self),
+            ScreenOp::RotateRow { row, by } => {
+                Some(ScreenOp::RotateRow { row, by: (cols - by % cols) % cols })
+            }
+            ScreenOp::RotateCol { col, by } => {
+                Some(ScreenOp::RotateCol { col, by: (rows - by % rows) % rows })
+            }
+            ScreenOp::Rect { .. } |
+            ScreenOp::Clear |
+            ScreenOp::ShiftRow { .. } |
+            ScreenOp::ShiftCol { .. } => None,
+        }
+    }
+
+    pub fn apply<S: Screen>(&self, screen: &mut S) {
+        match *self {
+            ScreenOp::Rect { col, row, width, height } => {
+                screen.fill_region((row, col), height, width, true);
+            }
+            ScreenOp::Invert { col, row, width, height } => {
+                screen.invert_region((row, col), height, width);
+            }
+            ScreenOp::Clear => {
+                let (rows, cols) = (screen.rows(), screen.cols());
+                screen.fill_region((0, 0), rows, cols, false);
+            }
+            ScreenOp::RotateRow { row, by } => {
+                let by = by % screen.cols();
+                screen.rotate_row_right(row, by);
+            }
+            ScreenOp::RotateCol { col, by } => {
+                let by = by % screen.rows();
+                screen.rotate_col_down(col, by);
+            }
+            ScreenOp::ShiftRow { row, by } => screen.shift_row_right(row, by),
+            ScreenOp::ShiftCol { col, by } => screen.shift_col_down(col, by),
+        }
+    }
+}
+
+fn parse_num(text: &str) -> Result<usize, OpError> {
+    text.parse().map_err(|_| OpError::BadNumber(text.to_string()))
+}
+
+// "AxB" -> (A, B)
+fn parse_size(text: &str) -> Result<(usize, usize), OpError> {
+    let mut parts = text.splitn(2, 'x');
+    match (parts.next(), parts.next()) {
+        (Some(a), Some(b)) => Ok((parse_num(a)?, parse_num(b)?)),
+        _ => Err(OpError::BadNumber(text.to_string())),
+    }
+}
+
+// "X,Y" -> (X, Y)
+fn parse_pos(text: &str) -> Result<(usize, usize), OpError> {
+    let mut parts = text.splitn(2, ',');
+    match (parts.next(), parts.next()) {
+        (Some(a), Some(b)) => Ok((parse_num(a)?, parse_num(b)?)),
+        _ => Err(OpError::BadNumber(text.to_string())),
+    }
+}
+
+// "x=A" or "y=A" -> A
+fn parse_axis(prefix: &str, text: &str) -> Result<usize, OpError> {
+    if !text.starts_with(prefix) {
+        return Err(OpError::Syntax(text.to_string()));
+    }
+    parse_num(&text[prefix.len()..])
+}
+
+impl FromStr for ScreenOp {
+    type Err = OpError;
+
+    fn from_str(line: &str) -> Result<ScreenOp, OpError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["rect", size] => {
+                let (width, height) = parse_size(size)?;
+                Ok(ScreenOp::Rect { col: 0, row: 0, width, height })
+            }
+            ["rect", size, "at", pos] => {
+                let (width, height) = parse_size(size)?;
+                let (col, row) = parse_pos(pos)?;
+                Ok(ScreenOp::Rect { col, row, width, height })
+            }
+            ["invert", size, "at", pos] => {
+                let (width, height) = parse_size(size)?;
+                let (col, row) = parse_pos(pos)?;
+                Ok(ScreenOp::Invert { col, row, width, height })
+            }
+            ["clear"] => Ok(ScreenOp::Clear),
+            ["rotate", "row", index, "by", by] => {
+                Ok(ScreenOp::RotateRow { row: parse_axis("y=", index)?, by: parse_num(by)? })
+            }
+            ["rotate", "column", index, "by", by] => {
+                Ok(ScreenOp::RotateCol { col: parse_axis("x=", index)?, by: parse_num(by)? })
+            }
+            ["shift", "row", index, "by", by] => {
+                Ok(ScreenOp::ShiftRow { row: parse_axis("y=", index)?, by: parse_num(by)? })
+            }
+            ["shift", "column", index, "by", by] => {
+                Ok(ScreenOp::ShiftCol { col: parse_axis("x=", index)?, by: parse_num(by)? })
+            }
+            _ => Err(OpError::Syntax(line.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ScreenOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScreenOp::Rect { col: 0, row: 0, width, height } => {
+                write!(f, "rect {}x{}", width, height)
+            }
+            ScreenOp::Rect { col, row, width, height } => {
+                write!(f, "rect {}x{} at {},{}", width, height, col, row)
+            }
+            ScreenOp::Invert { col, row, width, height } => {
+                write!(f, "invert {}x{} at {},{}", width, height, col, row)
+            }
+            ScreenOp::Clear => write!(f, "clear"),
+            ScreenOp::RotateRow { row, by } => write!(f, "rotate row y={} by {}", row, by),
+            ScreenOp::RotateCol { col, by } => write!(f, "rotate column x={} by {}", col, by),
+            ScreenOp::ShiftRow { row, by } => write!(f, "shift row y={} by {}", row, by),
+            ScreenOp::ShiftCol { col, by } => write!(f, "shift column x={} by {}", col, by),
+        }
+    }
+}
+
+// Parse and validate every line against a rows x cols screen. Blank lines are skipped.
+pub fn parse_program(input: &str, rows: usize, cols: usize) -> Result<Vec<ScreenOp>, ProgramError> {
+    input.lines()
+        .enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            line.parse::<ScreenOp>()
+                .and_then(|op| op.validate(rows, cols).map(|_| op))
+                .map_err(|error| ProgramError { line: i + 1, error })
+        })
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let lines = ["rect 3x2",
+                     "rect 1x4 at 2,1",
+                     "invert 2x2 at 0,0",
+                     "clear",
+                     "rotate row y=0 by 4",
+                     "rotate column x=1 by 1",
+                     "shift row y=2 by 3",
+                     "shift column x=5 by 1"];
+        for line in lines.iter() {
+            let op: ScreenOp = line.parse().unwrap();
+            assert_eq!(op.to_string(), *line);
+        }
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        let err = parse_program("rect 3x2\n\nrotate diagonal x=1 by 2", 3, 7).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.error, OpError::Syntax("rotate diagonal x=1 by 2".to_string()));
+
+        let err = parse_program("rect 3xq", 3, 7).unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.error, OpError::BadNumber("q".to_string()));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let err = parse_program("rect 3x2\nrotate row y=3 by 1", 3, 7).unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.error, OpError::OutOfRange { what: "row", value: 3, limit: 3 });
+
+        assert!(parse_program("rotate column x=6 by 1", 3, 7).is_ok());
+        assert!(parse_program("rotate column x=7 by 1", 3, 7).is_err());
+        assert!(parse_program("rect 2x2 at 6,0", 3, 7).is_err());
+        assert!(parse_program("rect 8x1", 3, 7).is_err());
+
+        // Offsets and sizes that overflow when added.
+        let err = parse_program("rect 18446744073709551615x1 at 1,0", 3, 7).unwrap_err();
+        assert_eq!(err.error, OpError::OutOfRange { what: "width", value: usize::MAX, limit: 7 });
+        let err = parse_program("invert 1x2 at 0,18446744073709551615", 3, 7).unwrap_err();
+        assert_eq!(err.error, OpError::OutOfRange { what: "height", value: 2, limit: 3 });
+    }
+
+    #[test]
+    fn new_ops() {
+        let mut screen = Matrix2D::new_with_default(3, 4, false);
+        "rect 2x2 at 1,1".parse::<ScreenOp>().unwrap().apply(&mut screen);
+        assert!(screen ==
+                Matrix2D::from_rowmajor_vec(3,
+                                            4,
+                                            vec![false, false, false, false,
+                                                 false, true, true, false,
+                                                 false, true, true, false]));
+
+        "invert 2x2 at 0,0".parse::<ScreenOp>().unwrap().apply(&mut screen);
+        "shift row y=2 by 2".parse::<ScreenOp>().unwrap().apply(&mut screen);
+        assert!(screen ==
+                Matrix2D::from_rowmajor_vec(3,
+                                            4,
+                                            vec![true, true, false, false,
+                                                 true, false, true, false,
+                                                 false, false, false, true]));
+
+        "shift column x=0 by 1".parse::<ScreenOp>().unwrap().apply(&mut screen);
+        assert!(!screen[(0, 0)] && screen[(1, 0)] && screen[(2, 0)]);
+
+        ScreenOp::Clear.apply(&mut screen);
+        assert!(screen.as_slice().iter().all(|&p| !p));
+    }
+    #[test]
+    fn oversized_invert_is_clipped() {
+        // Unvalidated ops past the edge must not wrap into the next row.
+        let mut screen = Matrix2D::new_with_default(2, 3, false);
+        ScreenOp::Invert { col: 1, row: 0, width: 5, height: 1 }.apply(&mut screen);
+        assert!(screen ==
+                Matrix2D::from_rowmajor_vec(2, 3, vec![false, true, true, false, false, false]));
+        ScreenOp::Invert { col: 0, row: 1, width: 3, height: 4 }.apply(&mut screen);
+        assert_eq!(screen.count_lit(), 5);
+    }
+}
@@ -0,0 +1,327 @@
+use std::io::{self, Write};
+
+use marker::{parse_marker, DecompressError};
+use stream::Format;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    // Bytes copied to the output as they are.
+    Literal(Vec<u8>),
+    // A marker and its block. In v1 the block is a single literal child.
+    Repeat {
+        width: usize,
+        count: u64,
+        children: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    // Offset in the input of the literal's first byte, or of the marker's '('.
+    pub pos: usize,
+    // Number of bytes this node expands to.
+    pub size: u64,
+    pub kind: NodeKind,
+}
+
+// The parsed form of a compressed input, with the decompressed size of every
+// part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ast {
+    pub format: Format,
+    pub nodes: Vec<Node>,
+    pub size: u64,
+}
+
+fn total_size(nodes: &[Node]) -> Result<u64, DecompressError> {
+    nodes.iter().try_fold(0u64, |total, node| {
+        total.checked_add(node.size).ok_or(DecompressError::LengthOverflow { pos: node.pos })
+    })
+}
+
+// Parse `input`, which starts at offset `base` of the whole input.
+fn parse_nodes(input: &[u8], base: usize, format: Format) -> Result<Vec<Node>, DecompressError> {
+    let mut nodes = Vec::new();
+    let mut literal: Option<Node> = None;
+    let mut pos = 0;
+    while pos < input.len() {
+        if input[pos] == b'(' {
+            nodes.extend(literal.take());
+
+            let marker = parse_marker(input, pos, base)?;
+            let block = &input[marker.start..marker.end];
+            let children = match format {
+                Format::V1 if block.is_empty() => Vec::new(),
+                Format::V1 => {
+                    vec![Node {
+                             pos: base + marker.start,
+                             size: block.len() as u64,
+                             kind: NodeKind::Literal(block.to_vec()),
+                         }]
+                }
+                Format::V2 => parse_nodes(block, base + marker.start, format)?,
+            };
+            let size = total_size(&children)?
+                .checked_mul(marker.count as u64)
+                .ok_or(DecompressError::LengthOverflow { pos: base + pos })?;
+            nodes.push(Node {
+                pos: base + pos,
+                size,
+                kind: NodeKind::Repeat {
+                    width: block.len(),
+                    count: marker.count as u64,
+                    children,
+                },
+            });
+            pos = marker.end;
+        } else {
+            // Skip non-printing ASCII characters <= 32.
+            if input[pos] > 32 {
+                let node = literal.get_or_insert(Node {
+                    pos: base + pos,
+                    size: 0,
+                    kind: NodeKind::Literal(Vec::new()),
+                });
+                if let NodeKind::Literal(ref mut bytes) = node.kind {
+                    bytes.push(input[pos]);
+                }
+                node.size += 1;
+            }
+            pos += 1;
+        }
+    }
+    nodes.extend(literal);
+    Ok(nodes)
+}
+
+// Characters that need escaping in both JSON strings and DOT labels.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 32 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Longest literal text shown in a DOT label.
+const MAX_LABEL_TEXT: usize = 16;
+
+impl Ast {
+    pub fn parse(input: &[u8], format: Format) -> Result<Ast, DecompressError> {
+        let nodes = parse_nodes(input, 0, format)?;
+        let size = total_size(&nodes)?;
+        Ok(Ast { format, nodes, size })
+    }
+
+    // Every repeat node at any depth, biggest expansion first.
+    pub fn largest_repeats(&self) -> Vec<&Node> {
+        fn collect<'a>(nodes: &'a [Node], repeats: &mut Vec<&'a Node>) {
+            for node in nodes {
+                if let NodeKind::Repeat { ref children, .. } = node.kind {
+                    repeats.push(node);
+                    collect(children, repeats);
+                }
+            }
+        }
+        let mut repeats = Vec::new();
+        collect(&self.nodes, &mut repeats);
+        repeats.sort_by(|a, b| b.size.cmp(&a.size).then(a.pos.cmp(&b.pos)));
+        repeats
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let format = match self.format {
+            Format::V1 => "v1",
+            Format::V2 => "v2",
+        };
+        write!(out, "{{\"format\":\"{}\",\"size\":{},\"nodes\":", format, self.size)?;
+        write_json_nodes(&self.nodes, out)?;
+        writeln!(out, "}}")
+    }
+
+    // A top-down graph of the expansion. Each node is labelled with where it
+    // is in the input and how many bytes it becomes.
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "digraph expansion {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        writeln!(out, "    root [label=\"input\\n{} bytes\"];", self.size)?;
+        let mut next_id = 0;
+        write_dot_nodes(&self.nodes, "root", &mut next_id, out)?;
+        writeln!(out, "}}")
+    }
+}
+
+fn write_json_nodes<W: Write>(nodes: &[Node], out: &mut W) -> io::Result<()> {
+    write!(out, "[")?;
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        match node.kind {
+            NodeKind::Literal(ref bytes) => {
+                write!(out,
+                       "{{\"type\":\"literal\",\"pos\":{},\"size\":{},\"text\":\"{}\"}}",
+                       node.pos,
+                       node.size,
+                       escape(bytes))?;
+            }
+            NodeKind::Repeat { width, count, ref children } => {
+                write!(out,
+                       "{{\"type\":\"repeat\",\"pos\":{},\"size\":{},\"width\":{},\"count\":{},\
+                        \"children\":",
+                       node.pos,
+                       node.size,
+                       width,
+                       count)?;
+                write_json_nodes(children, out)?;
+                write!(out, "}}")?;
+            }
+        }
+    }
+    write!(out, "]")
+}
+
+fn write_dot_nodes<W: Write>(nodes: &[Node],
+                             parent: &str,
+                             next_id: &mut usize,
+                             out: &mut W)
+                             -> io::Result<()> {
+    for node in nodes {
+        let id = format!("n{}", next_id);
+        *next_id += 1;
+        match node.kind {
+            NodeKind::Literal(ref bytes) => {
+                let text = if bytes.len() > MAX_LABEL_TEXT {
+                    format!("{}...", escape(&bytes[..MAX_LABEL_TEXT]))
+                } else {
+                    escape(bytes)
+                };
+                writeln!(out,
+                         "    {} [label=\"\\\"{}\\\" @{}\\n{} bytes\"];",
+                         id,
+                         text,
+                         node.pos,
+                         node.size)?;
+            }
+            NodeKind::Repeat { width, count, .. } => {
+                writeln!(out,
+                         "    {} [label=\"({}x{}) @{}\\n{} bytes\"];",
+                         id,
+                         width,
+                         count,
+                         node.pos,
+                         node.size)?;
+            }
+        }
+        writeln!(out, "    {} -> {};", parent, id)?;
+        if let NodeKind::Repeat { ref children, .. } = node.kind {
+            write_dot_nodes(children, &id, next_id, out)?;
+        }
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLES: [&str; 7] = ["ADVENT",
+                                 "A(1x5)BC",
+                                 "(3x3)XYZ",
+                                 "A(2x2)BCD(2x2)EFG",
+                                 "(6x1)(1x3)A",
+                                 "X(8x2)(3x3)ABCY\n",
+                                 "(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN"];
+
+    fn literal(pos: usize, text: &str) -> Node {
+        Node {
+            pos,
+            size: text.len() as u64,
+            kind: NodeKind::Literal(text.as_bytes().to_vec()),
+        }
+    }
+
+    #[test]
+    fn sizes_match_decompress() {
+        for input in EXAMPLES.iter() {
+            let input = input.as_bytes();
+            let v1 = Ast::parse(input, Format::V1).unwrap();
+            assert_eq!(v1.size, ::decompress(input).unwrap().len() as u64);
+            let v2 = Ast::parse(input, Format::V2).unwrap();
+            assert_eq!(Ok(v2.size), ::simulate_decompress_v2(input));
+        }
+    }
+
+    #[test]
+    fn tree_shape() {
+        let ast = Ast::parse(b"X(8x2)(3x3)ABCY\n", Format::V2).unwrap();
+        let inner = Node {
+            pos: 6,
+            size: 9,
+            kind: NodeKind::Repeat {
+                width: 3,
+                count: 3,
+                children: vec![literal(11, "ABC")],
+            },
+        };
+        let outer = Node {
+            pos: 1,
+            size: 18,
+            kind: NodeKind::Repeat {
+                width: 8,
+                count: 2,
+                children: vec![inner.clone()],
+            },
+        };
+        assert_eq!(ast.nodes, vec![literal(0, "X"), outer.clone(), literal(14, "Y")]);
+        assert_eq!(ast.largest_repeats(), vec![&outer, &inner]);
+
+        // v1 leaves the block as it is.
+        let ast = Ast::parse(b"X(8x2)(3x3)ABCY", Format::V1).unwrap();
+        match ast.nodes[1].kind {
+            NodeKind::Repeat { ref children, .. } => assert_eq!(*children, vec![literal(6, "(3x3)ABC")]),
+            _ => panic!("Expected a repeat node"),
+        }
+    }
+
+    #[test]
+    fn json_and_dot() {
+        let ast = Ast::parse(b"A(6x2)(1x3)\"", Format::V2).unwrap();
+
+        let mut json = Vec::new();
+        ast.write_json(&mut json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(),
+                   "{\"format\":\"v2\",\"size\":7,\"nodes\":[\
+                    {\"type\":\"literal\",\"pos\":0,\"size\":1,\"text\":\"A\"},\
+                    {\"type\":\"repeat\",\"pos\":1,\"size\":6,\"width\":6,\"count\":2,\"children\":[\
+                    {\"type\":\"repeat\",\"pos\":6,\"size\":3,\"width\":1,\"count\":3,\"children\":[\
+                    {\"type\":\"literal\",\"pos\":11,\"size\":1,\"text\":\"\\\"\"}]}]}]}\n");
+
+        let mut dot = Vec::new();
+        ast.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph expansion {\n"));
+        assert!(dot.contains("root [label=\"input\\n7 bytes\"];"));
+        assert!(dot.contains("n1 [label=\"(6x2) @1\\n6 bytes\"];\n    root -> n1;"));
+        assert!(dot.contains("n3 [label=\"\\\"\\\"\\\" @11\\n1 bytes\"];\n    n2 -> n3;"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Ast::parse(b"(7x2)AB(1xq)", Format::V2),
+                   Err(DecompressError::BadCount { pos: 7 }));
+        assert_eq!(Ast::parse(b"(6x2)AB(1xq)", Format::V1).map(|ast| ast.size),
+                   Ok(13));
+        assert_eq!(Ast::parse(b"(15x4294967296)(1x4294967296)A", Format::V2),
+                   Err(DecompressError::LengthOverflow { pos: 0 }));
+    }
+}
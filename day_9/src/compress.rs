@@ -0,0 +1,352 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use stream::Format;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressOptions {
+    pub format: Format,
+    // Longest repeated block to look for. Higher finds smaller encodings, at
+    // a cost of roughly O(input length * effort) per level of nesting.
+    pub effort: usize,
+}
+
+impl Default for CompressOptions {
+    fn default() -> CompressOptions {
+        CompressOptions {
+            format: Format::V1,
+            effort: 32,
+        }
+    }
+}
+
+// v2 has no way to escape text, so its input can't contain anything the
+// decoder would skip or parse: whitespace or '('.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressError {
+    pub pos: usize,
+    pub byte: u8,
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "byte 0x{:02x} at offset {} can't be encoded in v2", self.byte, self.pos)
+    }
+}
+
+impl Error for CompressError {
+    fn description(&self) -> &str {
+        "unencodable input"
+    }
+}
+
+// Bytes decompress() copies through unchanged outside of a marker block.
+fn is_plain(byte: u8) -> bool {
+    byte > 32 && byte != b'('
+}
+
+fn num_digits(mut n: usize) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+// Length of "(AxB)".
+fn marker_len(width: usize, count: usize) -> usize {
+    3 + num_digits(width) + num_digits(count)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Choice {
+    Literal,
+    // Block of `len` input bytes, repeated `count` times.
+    Repeat { len: usize, count: usize },
+}
+
+// For each block length, how many times the block starting at each position
+// repeats back to back. Built from the run of matching bytes at period `len`.
+struct Repeats {
+    counts: Vec<Vec<usize>>,
+}
+
+impl Repeats {
+    fn new(bytes: &[u8], max_len: usize) -> Repeats {
+        let n = bytes.len();
+        let mut counts = vec![Vec::new()];
+        for len in 1..=max_len {
+            let mut matching = vec![0usize; n + 1];
+            for i in (0..n.saturating_sub(len)).rev() {
+                if bytes[i] == bytes[i + len] {
+                    matching[i] = matching[i + 1] + 1;
+                }
+            }
+            counts.push(matching.iter().map(|&m| 1 + m / len).collect());
+        }
+        Repeats { counts }
+    }
+
+    fn count(&self, pos: usize, len: usize) -> usize {
+        self.counts[len][pos]
+    }
+}
+
+// Cheapest way to escape the text starting at each position with a single
+// "(Nx1)" block, for every N with a given number of digits. Every position is
+// fed in from the end of the input; this keeps a sliding window minimum of
+// `end + best[end]` over the block ends in range.
+struct EscapeWindow {
+    min_len: usize,
+    max_len: usize,
+    // Block ends, with the cheapest at the back.
+    ends: VecDeque<(usize, usize)>,
+}
+
+impl EscapeWindow {
+    fn new(digits: usize) -> EscapeWindow {
+        let min_len = 10usize.pow(digits as u32 - 1);
+        EscapeWindow {
+            min_len,
+            max_len: min_len * 10 - 1,
+            ends: VecDeque::new(),
+        }
+    }
+
+    // Returns (block length, total cost) of the best escape block at `pos`.
+    fn advance(&mut self, pos: usize, input: &str, best: &[usize]) -> Option<(usize, usize)> {
+        let end = pos + self.min_len;
+        if end < best.len() && best[end] != usize::MAX && input.is_char_boundary(end) {
+            let cost = end + best[end];
+            while self.ends.front().is_some_and(|&(_, c)| c >= cost) {
+                self.ends.pop_front();
+            }
+            self.ends.push_front((end, cost));
+        }
+        while self.ends.back().is_some_and(|&(end, _)| end > pos + self.max_len) {
+            self.ends.pop_back();
+        }
+        self.ends.back().map(|&(end, cost)| (end - pos, cost - pos + marker_len(self.min_len, 1)))
+    }
+}
+
+// Marker-format compressor. Finds the shortest encoding made of literal
+// bytes and back-to-back repeated blocks, within the search effort.
+pub struct Compressor {
+    options: CompressOptions,
+    // v2 only: best encoding of each block already considered.
+    inner: HashMap<Vec<u8>, String>,
+}
+
+impl Compressor {
+    pub fn new(options: CompressOptions) -> Compressor {
+        Compressor {
+            options,
+            inner: HashMap::new(),
+        }
+    }
+
+    pub fn compress(&mut self, input: &str) -> Result<String, CompressError> {
+        if self.options.format == Format::V2 {
+            if let Some(pos) = input.bytes().position(|b| !is_plain(b)) {
+                return Err(CompressError {
+                    pos,
+                    byte: input.as_bytes()[pos],
+                });
+            }
+        }
+        Ok(self.encode(input))
+    }
+
+    fn encode(&mut self, input: &str) -> String {
+        let bytes = input.as_bytes();
+        let n = bytes.len();
+        let v2 = self.options.format == Format::V2;
+
+        let max_len = self.options.effort.min(n);
+        let repeats = Repeats::new(bytes, max_len);
+
+        // v1 also needs count-1 blocks to escape anything that isn't plain.
+        let mut escapes = if v2 {
+            Vec::new()
+        } else {
+            (1..=num_digits(n)).map(EscapeWindow::new).collect()
+        };
+
+        // best[i] is the shortest encoding length of input[i..].
+        let mut best = vec![usize::MAX; n + 1];
+        let mut choice = vec![Choice::Literal; n + 1];
+        best[n] = 0;
+
+        for i in (0..n).rev() {
+            if is_plain(bytes[i]) {
+                best[i] = 1 + best[i + 1];
+            }
+            for window in escapes.iter_mut() {
+                match window.advance(i, input, &best) {
+                    Some((len, cost)) if cost < best[i] && input.is_char_boundary(i) => {
+                        best[i] = cost;
+                        choice[i] = Choice::Repeat { len, count: 1 };
+                    }
+                    _ => {}
+                }
+            }
+            if !input.is_char_boundary(i) {
+                continue;
+            }
+            for len in 1..=max_len.min(n - i) {
+                if !input.is_char_boundary(i + len) {
+                    continue;
+                }
+                let count = repeats.count(i, len);
+                if count < 2 {
+                    continue;
+                }
+
+                let mut consider = |count: usize, block_cost: usize, width: usize| {
+                    let end = i + len * count;
+                    if best[end] == usize::MAX {
+                        return;
+                    }
+                    let cost = marker_len(width, count) + block_cost + best[end];
+                    if cost < best[i] {
+                        best[i] = cost;
+                        choice[i] = Choice::Repeat { len, count };
+                    }
+                };
+
+                if v2 {
+                    let block = self.encode_block(&input[i..i + len]);
+                    consider(count, block.len(), block.len());
+                } else {
+                    consider(count, len, len);
+                }
+            }
+        }
+
+        let mut output = Vec::with_capacity(best[0]);
+        let mut i = 0;
+        while i < n {
+            match choice[i] {
+                Choice::Literal => {
+                    output.push(bytes[i]);
+                    i += 1;
+                }
+                Choice::Repeat { len, count } => {
+                    let block = &input[i..i + len];
+                    let block = if v2 { self.encode_block(block) } else { block.to_string() };
+                    output.extend_from_slice(format!("({}x{})", block.len(), count).as_bytes());
+                    output.extend_from_slice(block.as_bytes());
+                    i += len * count;
+                }
+            }
+        }
+        // Literal bytes and blocks are all whole characters of the input.
+        String::from_utf8(output).unwrap()
+    }
+
+    // Encoding of a v2 block's contents, cached by content.
+    fn encode_block(&mut self, block: &str) -> String {
+        if let Some(encoded) = self.inner.get(block.as_bytes()) {
+            return encoded.clone();
+        }
+        let encoded = self.encode(block);
+        self.inner.insert(block.as_bytes().to_vec(), encoded.clone());
+        encoded
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn compress(input: &str, options: CompressOptions) -> Result<String, CompressError> {
+        Compressor::new(options).compress(input)
+    }
+
+    fn v1(effort: usize) -> CompressOptions {
+        CompressOptions {
+            format: Format::V1,
+            effort,
+        }
+    }
+
+    fn v2(effort: usize) -> CompressOptions {
+        CompressOptions {
+            format: Format::V2,
+            effort,
+        }
+    }
+
+    #[test]
+    fn v1_round_trip() {
+        let inputs = ["",
+                      "ADVENT",
+                      "ABBBBBC",
+                      "XYZXYZXYZ",
+                      "(1x3)A",
+                      "X(3x3)ABC(3x3)ABCY",
+                      "some text with spaces\nand newlines\n",
+                      "unicode: åäö ✓✓✓✓✓✓✓✓",
+                      "((((((((((((((((((("];
+        for input in inputs.iter() {
+            for effort in [0, 1, 4, 32].iter() {
+                let encoded = compress(input, v1(*effort)).unwrap();
+                assert_eq!(::decompress(encoded.as_bytes()).unwrap(), input.as_bytes(), "Encoded as {:?}", encoded);
+            }
+        }
+    }
+
+    #[test]
+    fn v1_finds_repeats() {
+        assert_eq!(compress("XYZXYZXYZ", v1(8)).unwrap(), "(3x3)XYZ");
+        assert_eq!(compress("ABBBBBBBBBBC", v1(8)).unwrap().len(), "A(1x10)BC".len());
+        assert_eq!(compress("ADVENT", v1(8)).unwrap(), "ADVENT");
+        assert_eq!(compress("a b", v1(8)).unwrap(), "a(1x1) b");
+
+        // Unrepeated text that needs escaping goes in one block, however long.
+        let text: String = (0..300).map(|i| if i % 7 == 0 || i == 299 { '(' } else { 'x' }).collect();
+        assert_eq!(compress(&text, v1(0)).unwrap(), format!("(300x1){}", text));
+
+        // Without any search effort, repeats are left alone.
+        assert_eq!(compress("XYZXYZXYZ", v1(0)).unwrap(), "XYZXYZXYZ");
+    }
+
+    #[test]
+    fn v2_nests_markers() {
+        let input = "XABCABCABCABCABCABCY";
+        let encoded = compress(input, v2(16)).unwrap();
+        assert!(encoded.len() < input.len());
+        assert_eq!(::decompress_v2(encoded.as_bytes()).unwrap(), input.as_bytes());
+
+        let input = "AAAAAAAAAABAAAAAAAAAABAAAAAAAAAAB";
+        let encoded = compress(input, v2(16)).unwrap();
+        assert_eq!(encoded.len(), "(8x3)(1x10)AB".len());
+        assert_eq!(::decompress_v2(encoded.as_bytes()).unwrap(), input.as_bytes());
+        assert_eq!(::simulate_decompress_v2(encoded.as_bytes()), Ok(input.len() as u64));
+    }
+
+    #[test]
+    fn v2_round_trip() {
+        let inputs = ["ADVENT",
+                      "ABCABCXYXYXYABCABCXYXYXY",
+                      "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                      "åäöåäöåäö✓✓✓✓"];
+        for input in inputs.iter() {
+            for effort in [0, 2, 8, 64].iter() {
+                let encoded = compress(input, v2(*effort)).unwrap();
+                assert_eq!(::decompress_v2(encoded.as_bytes()).unwrap(), input.as_bytes(), "Encoded as {:?}", encoded);
+            }
+        }
+    }
+
+    #[test]
+    fn v2_rejects_unencodable() {
+        assert_eq!(compress("AB(C", v2(8)), Err(CompressError { pos: 2, byte: b'(' }));
+        assert_eq!(compress("AB C", v2(8)), Err(CompressError { pos: 2, byte: b' ' }));
+    }
+}
@@ -0,0 +1,199 @@
+// Random access into the v2 output without expanding it. Repeated blocks are
+// sized with simulate_decompress_v2() and skipped over whole, so a lookup
+// only descends into the one repeat of each block that holds the offset.
+
+use marker::{parse_marker, DecompressError};
+
+enum Token<'a> {
+    Byte(u8),
+    // A repeated block, which starts at `base` in the original input. Its
+    // marker is at `marker`.
+    Block { block: &'a [u8], base: usize, count: u64, marker: usize },
+}
+
+// Top-level tokens of `input`, skipping non-printing characters.
+fn tokens(input: &[u8], base: usize) -> Result<Vec<Token<'_>>, DecompressError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        if input[pos] == b'(' {
+            let marker = parse_marker(input, pos, base)?;
+            tokens.push(Token::Block {
+                block: &input[marker.start..marker.end],
+                base: base + marker.start,
+                count: marker.count as u64,
+                marker: base + pos,
+            });
+            pos = marker.end;
+        } else {
+            if input[pos] > 32 {
+                tokens.push(Token::Byte(input[pos]));
+            }
+            pos += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+fn repeated_size(size: u64, count: u64, marker: usize) -> Result<u64, DecompressError> {
+    size.checked_mul(count).ok_or(DecompressError::LengthOverflow { pos: marker })
+}
+
+// The byte at `offset` of the v2 output, or None if the output is shorter.
+pub fn byte_at_v2(input: &[u8], offset: u64) -> Result<Option<u8>, DecompressError> {
+    byte_at_v2_at(input, 0, offset)
+}
+
+fn byte_at_v2_at(input: &[u8], base: usize, mut offset: u64) -> Result<Option<u8>, DecompressError> {
+    for token in tokens(input, base)? {
+        match token {
+            Token::Byte(byte) => {
+                if offset == 0 {
+                    return Ok(Some(byte));
+                }
+                offset -= 1;
+            }
+            Token::Block { block, base, count, marker } => {
+                let size = ::simulate_decompress_v2_at(block, base)?;
+                let total = repeated_size(size, count, marker)?;
+                if offset < total {
+                    return byte_at_v2_at(block, base, offset % size);
+                }
+                offset -= total;
+            }
+        }
+    }
+    Ok(None)
+}
+
+// Bytes [start, end) of the v2 output, cut short if the output ends first.
+pub fn range_v2(input: &[u8], start: u64, end: u64) -> Result<Vec<u8>, DecompressError> {
+    // Reserve only what the output can actually supply, not what was asked for.
+    let size = ::simulate_decompress_v2(input)?;
+    let mut output = Vec::with_capacity(end.min(size).saturating_sub(start) as usize);
+    append_range_v2(input, 0, start, end, &mut output)?;
+    Ok(output)
+}
+
+// Where `input` expands to output offsets [0, size), append the part that
+// falls inside [start, end).
+fn append_range_v2(input: &[u8],
+                   base: usize,
+                   start: u64,
+                   end: u64,
+                   output: &mut Vec<u8>)
+                   -> Result<(), DecompressError> {
+    let mut pos = 0;
+    for token in tokens(input, base)? {
+        if pos >= end {
+            break;
+        }
+        match token {
+            Token::Byte(byte) => {
+                if pos >= start {
+                    output.push(byte);
+                }
+                pos += 1;
+            }
+            Token::Block { block, base, count, marker } => {
+                let size = ::simulate_decompress_v2_at(block, base)?;
+                let total = repeated_size(size, count, marker)?;
+                let next = pos.checked_add(total).ok_or(DecompressError::LengthOverflow { pos: marker })?;
+                // Empty blocks, and blocks repeated no times, produce nothing.
+                if total > 0 && next > start {
+                    // Only visit the repeats that overlap the range.
+                    let first = start.saturating_sub(pos) / size;
+                    let last = ((end - pos - 1) / size).min(count - 1);
+                    for repeat in first..=last {
+                        let repeat_start = pos + repeat * size;
+                        append_range_v2(block,
+                                        base,
+                                        start.saturating_sub(repeat_start),
+                                        end - repeat_start,
+                                        output)?;
+                    }
+                }
+                pos = next;
+            }
+        }
+    }
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLES: [&str; 8] = ["ADVENT",
+                                 "A(1x5)BC",
+                                 "(3x3)XYZ",
+                                 "A(2x2)BCD(2x2)EFG",
+                                 "(6x1)(1x3)A",
+                                 "X(8x2)(3x3)ABCY\n",
+                                 "(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN",
+                                 "AB(1x0)CD"];
+
+    #[test]
+    fn matches_decompress_v2() {
+        for input in EXAMPLES.iter() {
+            let input = input.as_bytes();
+            let output = ::decompress_v2(input).unwrap();
+            for (offset, &byte) in output.iter().enumerate() {
+                assert_eq!(byte_at_v2(input, offset as u64), Ok(Some(byte)));
+            }
+            assert_eq!(byte_at_v2(input, output.len() as u64), Ok(None));
+
+            let len = output.len() as u64;
+            for start in 0..len + 1 {
+                for end in start..len + 3 {
+                    assert_eq!(range_v2(input, start, end).unwrap(),
+                               &output[start as usize..end.min(len) as usize],
+                               "Range [{}, {}) of {:?}",
+                               start,
+                               end,
+                               input);
+                }
+            }
+        }
+
+        // A block repeated no times, after some output.
+        assert_eq!(range_v2(b"AB(1x0)CD", 0, 4).unwrap(), b"ABD");
+    }
+
+    #[test]
+    fn huge_output() {
+        // 1 + 9 * 10^10 + 1 bytes.
+        let input = b"X(19x100000)(9x100000)ABCDEFGHIY";
+        let size = ::simulate_decompress_v2(input).unwrap();
+        assert_eq!(size, 90_000_000_002);
+
+        assert_eq!(byte_at_v2(input, 0), Ok(Some(b'X')));
+        assert_eq!(byte_at_v2(input, 1), Ok(Some(b'A')));
+        assert_eq!(byte_at_v2(input, 1 + 45_000_000_004), Ok(Some(b'E')));
+        assert_eq!(byte_at_v2(input, size - 1), Ok(Some(b'Y')));
+        assert_eq!(byte_at_v2(input, size), Ok(None));
+
+        assert_eq!(range_v2(input, 50_000_000_000, 50_000_000_012).unwrap(), b"EFGHIABCDEFG");
+        assert_eq!(range_v2(input, size - 4, size + 10).unwrap(), b"GHIY");
+    }
+
+    #[test]
+    fn end_past_output() {
+        assert_eq!(range_v2(b"ABC", 1, u64::MAX).unwrap(), b"BC");
+        assert_eq!(range_v2(b"ABC", 5, u64::MAX).unwrap(), b"");
+        assert_eq!(range_v2(b"X(19x100000)(9x100000)ABCDEFGHIY", 90_000_000_000, u64::MAX).unwrap(),
+                   b"IY");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(byte_at_v2(b"AB(3x2", 0),
+                   Err(DecompressError::UnterminatedMarker { pos: 2 }));
+        assert_eq!(range_v2(b"AB(6x2)C(1xq)", 0, 4),
+                   Err(DecompressError::BadCount { pos: 8 }));
+        assert_eq!(byte_at_v2(b"(15x4294967296)(1x4294967296)A", 5),
+                   Err(DecompressError::LengthOverflow { pos: 0 }));
+    }
+}
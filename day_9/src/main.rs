@@ -1,176 +1,257 @@
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 
-fn decompress(input: &str) -> String {
-    let mut output = String::new();
+mod ast;
+mod compress;
+mod lookup;
+mod marker;
+mod stream;
+use ast::Ast;
+use compress::{CompressOptions, Compressor};
+use lookup::{byte_at_v2, range_v2};
+use marker::{parse_marker, DecompressError};
+use stream::{Decoder, Format};
+
+fn decompress(input: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let mut output = Vec::new();
 
-    let bytes = input.as_bytes();
     let mut tag_open_idx = 0;
-    while tag_open_idx < bytes.len() {
-        let next_chr = bytes[tag_open_idx] as char;
-        //println!("checking '{}' (code {})", next_chr, bytes[tag_open_idx]);
-        if next_chr == '(' {
-            let sub_array = &bytes[tag_open_idx..bytes.len()];
-            if let Some(close_offset) = sub_array.iter().position(|&x| x as char == ')') {
-                let tag_close_idx = tag_open_idx + close_offset;
-                let tag_string = &input[tag_open_idx + 1..tag_close_idx];
-                let tag_values = tag_string.split('x')
-                    .map(|x| x.parse::<usize>().unwrap())
-                    .collect::<Vec<_>>();
-                let block_width = tag_values[0];
-                let block_count = tag_values[1];
-
-                let block_start = tag_close_idx+1;
-                let block_end = block_start+block_width;
-                let block = &input[block_start..block_end];
-                //println!("Block: {:?}, count {}", block, block_count);
-                for _ in 0..block_count {
-                    output.push_str(block);
-                }
-
-                //println!("Tag '{}' parsed as {}x{}", &tag_string, block_width, block_count);
-                //println!("Tag @ [{},{}], data block [{}]", tag_open_idx, tag_close_idx, block);
-                //println!("Tag [{}], data block [{}]", &tag_string, block);
-
-                // Advance the processing index to after the decompressed block.
-                tag_open_idx = block_end;
-            } else {
-                panic!("Unable to parse tag");
+    while tag_open_idx < input.len() {
+        if input[tag_open_idx] == b'(' {
+            let marker = parse_marker(input, tag_open_idx, 0)?;
+            let block = &input[marker.start..marker.end];
+            for _ in 0..marker.count {
+                output.extend_from_slice(block);
             }
-        }
-        else if bytes[tag_open_idx] <= 32 {
+
+            // Advance the processing index to after the decompressed block.
+            tag_open_idx = marker.end;
+        } else if input[tag_open_idx] <= 32 {
             // Skip non-printing ASCII characters <= 32.
             tag_open_idx += 1;
         } else {
             // No change. Copy byte to the output.
-            output.push(bytes[tag_open_idx] as char);
+            output.push(input[tag_open_idx]);
             tag_open_idx += 1;
         }
     }
-    //println!("Output: {}", output);
-    output
+    Ok(output)
 }
 
 // Return the number bytes in the output if 'input' were decompressed.
-fn simulate_decompress_v2(input: &str) -> usize {
-    let mut output_size = 0;
+fn simulate_decompress_v2(input: &[u8]) -> Result<u64, DecompressError> {
+    simulate_decompress_v2_at(input, 0)
+}
+
+// As above, for a block found at offset `base` of the original input.
+fn simulate_decompress_v2_at(input: &[u8], base: usize) -> Result<u64, DecompressError> {
+    let mut output_size: u64 = 0;
 
-    let bytes = input.as_bytes();
     let mut tag_open_idx = 0;
-    while tag_open_idx < bytes.len() {
-        let next_chr = bytes[tag_open_idx] as char;
-        if next_chr == '(' {
-            let sub_array = &bytes[tag_open_idx..bytes.len()];
-            if let Some(close_offset) = sub_array.iter().position(|&x| x as char == ')') {
-                let tag_close_idx = tag_open_idx + close_offset;
-                let tag_string = &input[tag_open_idx + 1..tag_close_idx];
-                let tag_values = tag_string.split('x')
-                    .map(|x| x.parse::<usize>().unwrap())
-                    .collect::<Vec<_>>();
-                let block_width = tag_values[0];
-                let block_count = tag_values[1];
-
-                let block_start = tag_close_idx+1;
-                let block_end = block_start+block_width;
-                let block = &input[block_start..block_end];
-
-                // Assuming there are no dependencies between blocks, we can just multiply the size.
-                let decompressed_block_size = simulate_decompress_v2(&block);
-                output_size += block_count * decompressed_block_size;
-
-                // Advance the processing index to after the decompressed block.
-                tag_open_idx = block_end;
-            } else {
-                panic!("Unable to parse tag");
-            }
+    while tag_open_idx < input.len() {
+        if input[tag_open_idx] == b'(' {
+            let marker = parse_marker(input, tag_open_idx, base)?;
+            let block = &input[marker.start..marker.end];
+
+            // Assuming there are no dependencies between blocks, we can just multiply the size.
+            let decompressed_block_size = simulate_decompress_v2_at(block, base + marker.start)?;
+            output_size = decompressed_block_size.checked_mul(marker.count as u64)
+                .and_then(|block_total| output_size.checked_add(block_total))
+                .ok_or(DecompressError::LengthOverflow { pos: base + tag_open_idx })?;
+
+            // Advance the processing index to after the decompressed block.
+            tag_open_idx = marker.end;
+        } else if input[tag_open_idx] <= 32 {
+            // Skip non-printing ASCII characters <= 32.
+            tag_open_idx += 1;
+        } else {
+            // No change. Add size to output.
+            output_size = output_size.checked_add(1)
+                .ok_or(DecompressError::LengthOverflow { pos: base + tag_open_idx })?;
+            tag_open_idx += 1;
         }
-            else if bytes[tag_open_idx] <= 32 {
-                // Skip non-printing ASCII characters <= 32.
-                tag_open_idx += 1;
-            } else {
-                // No change. Add size to output.
-                output_size += 1;
-                tag_open_idx += 1;
-            }
     }
-    //println!("Output: {}", output);
-    output_size
+    Ok(output_size)
 }
 
 // Iterate decompress() until all symbols are expanded.
 #[allow(dead_code)]
-fn decompress_v2(input: &str) -> String {
+fn decompress_v2(input: &[u8]) -> Result<Vec<u8>, DecompressError> {
     let mut last_length = input.len();
-    let mut output = decompress(input);
+    let mut output = decompress(input)?;
     while last_length != output.len() {
         last_length = output.len();
-        output = decompress(&output);
+        output = decompress(&output)?;
     }
-    output
+    Ok(output)
 }
 
 fn main() {
-    let mut input_string = String::new();
+    // `--stream v1|v2` decompresses stdin to stdout without buffering the output.
+    // `--compress v1|v2 [--effort N]` does the reverse, buffering all of stdin.
+    // `--tree v1|v2 json|dot` prints the expansion tree of stdin.
+    let args: Vec<String> = std::env::args().collect();
+    let format_arg = |i: usize| match args.get(i + 1).map(|s| s.as_str()) {
+        Some("v1") => Format::V1,
+        Some("v2") => Format::V2,
+        _ => panic!("Usage: {} v1|v2", args[i]),
+    };
+    if let Some(i) = args.iter().position(|arg| arg == "--stream") {
+        let format = format_arg(i);
+        let stdout = io::stdout();
+        let mut decoder = Decoder::new(io::stdin(), format);
+        match io::copy(&mut decoder, &mut stdout.lock()) {
+            // The reader downstream stopped early (e.g. `head`); that's fine.
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                eprintln!("Unable to decompress: {}", e);
+                std::process::exit(1);
+            }
+            Ok(_) => {}
+        }
+        return;
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--compress") {
+        let mut options = CompressOptions { format: format_arg(i), ..Default::default() };
+        if let Some(j) = args.iter().position(|arg| arg == "--effort") {
+            options.effort = args.get(j + 1)
+                .and_then(|s| s.parse().ok())
+                .expect("Usage: --effort N");
+        }
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).unwrap();
+        let output = Compressor::new(options).compress(&input).unwrap_or_else(|e| panic!("{}", e));
+        println!("{}", output);
+        eprintln!("Compressed {} bytes to {} ({:.1}%)",
+                  input.len(),
+                  output.len(),
+                  100.0 * output.len() as f64 / input.len().max(1) as f64);
+        return;
+    }
+
+    if let Some(i) = args.iter().position(|arg| arg == "--tree") {
+        let mut input = Vec::new();
+        io::stdin().read_to_end(&mut input).unwrap();
+        let ast = Ast::parse(&input, format_arg(i)).unwrap_or_else(|e| panic!("{}", e));
+        let stdout = io::stdout();
+        match args.get(i + 2).map(|s| s.as_str()) {
+            Some("json") => ast.write_json(&mut stdout.lock()).unwrap(),
+            Some("dot") => ast.write_dot(&mut stdout.lock()).unwrap(),
+            _ => panic!("Usage: --tree v1|v2 json|dot"),
+        }
+        return;
+    }
+
+    let mut input = Vec::new();
     let mut file = File::open("input.txt").unwrap();
-    let _ = file.read_to_string(&mut input_string);
+    let _ = file.read_to_end(&mut input);
 
     {
-        let output = decompress(&input_string);
+        let output = decompress(&input).unwrap_or_else(|e| panic!("{}", e));
         println!("Part 1: length {} bytes", output.len());
         assert!(output.len() == 123908);
+
+        let mut streamed = Vec::new();
+        Decoder::new(&input[..], Format::V1).read_to_end(&mut streamed).unwrap();
+        assert!(streamed == output);
+
+        // Squeeze the expanded text back down and check it survives the trip.
+        let text = std::str::from_utf8(&output).unwrap();
+        let recompressed = Compressor::new(CompressOptions::default()).compress(text).unwrap();
+        println!("Part 1: recompressed to {} bytes", recompressed.len());
+        assert!(decompress(recompressed.as_bytes()).unwrap() == output);
     }
 
     {
-        let output_2_len = simulate_decompress_v2(&input_string);
+        let output_2_len = simulate_decompress_v2(&input).unwrap_or_else(|e| panic!("{}", e));
         println!("Part 2: length {} bytes", output_2_len);
         assert!(output_2_len == 10755693147);
+
+        // Spot-check the middle of the output without expanding the rest.
+        let middle = output_2_len / 2;
+        let window = range_v2(&input, middle, middle + 16).unwrap();
+        println!("Part 2: bytes at {}: {}", middle, String::from_utf8_lossy(&window));
+        assert!(byte_at_v2(&input, middle).unwrap() == Some(window[0]));
+        assert!(byte_at_v2(&input, output_2_len).unwrap().is_none());
+
+        let ast = Ast::parse(&input, Format::V2).unwrap();
+        assert!(ast.size == output_2_len);
+        let largest = ast.largest_repeats()[0];
+        println!("Part 2: largest marker at offset {} expands to {} bytes",
+                 largest.pos,
+                 largest.size);
     }
 }
 
 #[test]
 fn test_format1() {
-    assert!(decompress("ADVENT") == "ADVENT");
-    assert!(decompress("ADVENT").len() == 6);
+    assert!(decompress(b"ADVENT").unwrap() == b"ADVENT");
+    assert!(decompress(b"ADVENT").unwrap().len() == 6);
 
-    assert!(decompress("A(1x5)BC") == "ABBBBBC");
-    assert!(decompress("A(1x5)BC").len() == 7);
+    assert!(decompress(b"A(1x5)BC").unwrap() == b"ABBBBBC");
+    assert!(decompress(b"A(1x5)BC").unwrap().len() == 7);
 
-    assert!(decompress("(3x3)XYZ") == "XYZXYZXYZ");
-    assert!(decompress("(3x3)XYZ").len() == 9);
+    assert!(decompress(b"(3x3)XYZ").unwrap() == b"XYZXYZXYZ");
+    assert!(decompress(b"(3x3)XYZ").unwrap().len() == 9);
 
-    assert!(decompress("A(2x2)BCD(2x2)EFG") == "ABCBCDEFEFG");
-    assert!(decompress("A(2x2)BCD(2x2)EFG").len() == 11);
+    assert!(decompress(b"A(2x2)BCD(2x2)EFG").unwrap() == b"ABCBCDEFEFG");
+    assert!(decompress(b"A(2x2)BCD(2x2)EFG").unwrap().len() == 11);
 
-    assert!(decompress("(6x1)(1x3)A") == "(1x3)A");
-    assert!(decompress("(6x1)(1x3)A").len() == 6);
+    assert!(decompress(b"(6x1)(1x3)A").unwrap() == b"(1x3)A");
+    assert!(decompress(b"(6x1)(1x3)A").unwrap().len() == 6);
 
-    assert!(decompress("X(8x2)(3x3)ABCY\n") == "X(3x3)ABC(3x3)ABCY");
-    assert!(decompress("X(8x2)(3x3)ABCY\n").len() == 18);
+    assert!(decompress(b"X(8x2)(3x3)ABCY\n").unwrap() == b"X(3x3)ABC(3x3)ABCY");
+    assert!(decompress(b"X(8x2)(3x3)ABCY\n").unwrap().len() == 18);
 }
 
 #[test]
 fn test_format2() {
-    assert!(decompress_v2("ADVENT") == "ADVENT");
-    assert!(decompress_v2("A(1x5)BC") == "ABBBBBC");
-    assert!(decompress_v2("(3x3)XYZ") == "XYZXYZXYZ");
-    assert!(decompress_v2("A(2x2)BCD(2x2)EFG") == "ABCBCDEFEFG");
-    assert!(decompress_v2("(6x1)(1x3)A") == "AAA");
-    assert!(decompress_v2("X(8x2)(3x3)ABCY") == "XABCABCABCABCABCABCY");
+    assert!(decompress_v2(b"ADVENT").unwrap() == b"ADVENT");
+    assert!(decompress_v2(b"A(1x5)BC").unwrap() == b"ABBBBBC");
+    assert!(decompress_v2(b"(3x3)XYZ").unwrap() == b"XYZXYZXYZ");
+    assert!(decompress_v2(b"A(2x2)BCD(2x2)EFG").unwrap() == b"ABCBCDEFEFG");
+    assert!(decompress_v2(b"(6x1)(1x3)A").unwrap() == b"AAA");
+    assert!(decompress_v2(b"X(8x2)(3x3)ABCY").unwrap() == b"XABCABCABCABCABCABCY");
 
     // Output is too long to type, so just compare the output length.
-    assert!(decompress_v2("(27x12)(20x12)(13x14)(7x10)(1x12)A").len() == 241920);
-    assert!(decompress_v2("(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN").len() == 445);
+    assert!(decompress_v2(b"(27x12)(20x12)(13x14)(7x10)(1x12)A").unwrap().len() == 241920);
+    assert!(decompress_v2(b"(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN").unwrap().len() ==
+            445);
 }
 
 #[test]
 fn test_format2_simulate() {
-    assert!(simulate_decompress_v2("ADVENT") == 6);
-    assert!(simulate_decompress_v2("A(1x5)BC") == 7);
-    assert!(simulate_decompress_v2("(3x3)XYZ") == 9);
-    assert!(simulate_decompress_v2("A(2x2)BCD(2x2)EFG") == 11);
-    assert!(simulate_decompress_v2("(6x1)(1x3)A") == 3);
-    assert!(simulate_decompress_v2("X(8x2)(3x3)ABCY") == 20);
-    assert!(simulate_decompress_v2("(27x12)(20x12)(13x14)(7x10)(1x12)A") == 241920);
-    assert!(simulate_decompress_v2("(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN") == 445);
-}
\ No newline at end of file
+    assert!(simulate_decompress_v2(b"ADVENT").unwrap() == 6);
+    assert!(simulate_decompress_v2(b"A(1x5)BC").unwrap() == 7);
+    assert!(simulate_decompress_v2(b"(3x3)XYZ").unwrap() == 9);
+    assert!(simulate_decompress_v2(b"A(2x2)BCD(2x2)EFG").unwrap() == 11);
+    assert!(simulate_decompress_v2(b"(6x1)(1x3)A").unwrap() == 3);
+    assert!(simulate_decompress_v2(b"X(8x2)(3x3)ABCY").unwrap() == 20);
+    assert!(simulate_decompress_v2(b"(27x12)(20x12)(13x14)(7x10)(1x12)A").unwrap() == 241920);
+    assert!(simulate_decompress_v2(b"(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN").unwrap() ==
+            445);
+}
+
+#[test]
+fn test_errors() {
+    assert!(decompress(b"AB(3x2") == Err(DecompressError::UnterminatedMarker { pos: 2 }));
+    assert!(decompress(b"A(3xq)ABC") == Err(DecompressError::BadCount { pos: 1 }));
+    assert!(decompress(b"(5x2)AB") ==
+            Err(DecompressError::BlockPastEnd { pos: 0, width: 5, available: 2 }));
+
+    // Non-ASCII bytes are copied through, and blocks may split UTF-8 characters.
+    assert!(decompress(b"(1x2)\xc3\xa5").unwrap() == b"\xc3\xc3\xa5");
+
+    // Errors inside nested blocks report their offset in the whole input.
+    assert!(simulate_decompress_v2(b"XY(7x2)(3x3)AB") ==
+            Err(DecompressError::BlockPastEnd { pos: 7, width: 3, available: 2 }));
+    assert!(simulate_decompress_v2(b"(10x2)AB(1x)CDEF") ==
+            Err(DecompressError::BadCount { pos: 8 }));
+
+    // 2^64 bytes: (2^32)^2.
+    assert!(simulate_decompress_v2(b"(15x4294967296)(1x4294967296)A") ==
+            Err(DecompressError::LengthOverflow { pos: 0 }));
+    assert!(simulate_decompress_v2(b"(15x4294967296)(1x4294967295)A").unwrap() ==
+            u64::MAX - 4294967295);
+}
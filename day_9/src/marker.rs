@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::fmt;
+
+// Why a compressed input couldn't be decoded. `pos` is the byte offset of the
+// marker's '(' in the original input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecompressError {
+    // A '(' with no ')' after it.
+    UnterminatedMarker { pos: usize },
+    // The marker text isn't "AxB" with two numbers.
+    BadCount { pos: usize },
+    // The marker's block needs more bytes than are left in the input.
+    BlockPastEnd { pos: usize, width: usize, available: usize },
+    // The v2 output would be longer than u64::MAX bytes.
+    LengthOverflow { pos: usize },
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecompressError::UnterminatedMarker { pos } => {
+                write!(f, "unterminated marker at offset {}", pos)
+            }
+            DecompressError::BadCount { pos } => write!(f, "unable to parse marker at offset {}", pos),
+            DecompressError::BlockPastEnd { pos, width, available } => {
+                write!(f,
+                       "marker at offset {} repeats {} bytes but only {} remain",
+                       pos,
+                       width,
+                       available)
+            }
+            DecompressError::LengthOverflow { pos } => {
+                write!(f, "decompressed length overflows at marker at offset {}", pos)
+            }
+        }
+    }
+}
+
+impl Error for DecompressError {
+    fn description(&self) -> &str {
+        "invalid compressed input"
+    }
+}
+
+// A parsed "(AxB)" marker and the block it repeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Marker {
+    pub count: usize,
+    // The block is input[start..end]; decoding carries on from `end`.
+    pub start: usize,
+    pub end: usize,
+}
+
+// "AxB" -> (A, B), for the text between a marker's parentheses. `offset` is
+// where the marker's '(' is in the original input.
+pub fn parse_marker_text(text: &[u8], offset: usize) -> Result<(usize, usize), DecompressError> {
+    let bad_count = DecompressError::BadCount { pos: offset };
+    let text = ::std::str::from_utf8(text).map_err(|_| bad_count.clone())?;
+    let mut parts = text.splitn(2, 'x');
+    match (parts.next().map(str::parse::<usize>), parts.next().map(str::parse::<usize>)) {
+        (Some(Ok(width)), Some(Ok(count))) => Ok((width, count)),
+        _ => Err(bad_count),
+    }
+}
+
+// Parse the marker whose '(' is at input[pos]. `base` is the offset of `input`
+// within the original input, for error reporting.
+pub fn parse_marker(input: &[u8], pos: usize, base: usize) -> Result<Marker, DecompressError> {
+    let offset = base + pos;
+    let close = input[pos..]
+        .iter()
+        .position(|&b| b == b')')
+        .map(|i| pos + i)
+        .ok_or(DecompressError::UnterminatedMarker { pos: offset })?;
+
+    let (width, count) = parse_marker_text(&input[pos + 1..close], offset)?;
+
+    let start = close + 1;
+    let available = input.len() - start;
+    if width > available {
+        return Err(DecompressError::BlockPastEnd { pos: offset, width, available });
+    }
+    Ok(Marker { count, start, end: start + width })
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_marker(b"A(2x3)BCD", 1, 0),
+                   Ok(Marker { count: 3, start: 6, end: 8 }));
+        assert_eq!(parse_marker(b"A(2x3", 1, 10),
+                   Err(DecompressError::UnterminatedMarker { pos: 11 }));
+        assert_eq!(parse_marker(b"(2y3)AB", 0, 0),
+                   Err(DecompressError::BadCount { pos: 0 }));
+        assert_eq!(parse_marker(b"(2x-3)AB", 0, 0),
+                   Err(DecompressError::BadCount { pos: 0 }));
+        assert_eq!(parse_marker(b"(99999999999999999999x1)AB", 0, 0),
+                   Err(DecompressError::BadCount { pos: 0 }));
+        assert_eq!(parse_marker(b"(5x1)AB", 0, 0),
+                   Err(DecompressError::BlockPastEnd { pos: 0, width: 5, available: 2 }));
+    }
+}
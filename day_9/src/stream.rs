@@ -0,0 +1,312 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::rc::Rc;
+
+use marker::{self, DecompressError};
+
+// Longest marker text accepted between the parentheses, e.g. "123x456".
+const MAX_MARKER_LEN: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // Repeated blocks are copied verbatim.
+    V1,
+    // Markers inside repeated blocks are expanded too.
+    V2,
+}
+
+// A block being repeated. Nested v2 blocks share their parent's buffer.
+struct Frame {
+    data: Rc<Vec<u8>>,
+    // Offset of data[0] in the input, for errors.
+    base: usize,
+    start: usize,
+    end: usize,
+    pos: usize,
+    repeats_left: usize,
+}
+
+// Decompresses the marker format lazily from any reader. Memory use is bounded
+// by the widest marker block in the input, not by the size of the output.
+pub struct Decoder<R: Read> {
+    reader: BufReader<R>,
+    format: Format,
+    // Blocks being repeated; the innermost is last.
+    stack: Vec<Frame>,
+    // Bytes read from `reader` so far.
+    input_pos: usize,
+    // An error hit after some bytes were decoded, returned by the next read.
+    pending: Option<io::Error>,
+}
+
+// Decoding errors are passed on as InvalidData, carrying the DecompressError.
+fn invalid(error: DecompressError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(reader: R, format: Format) -> Decoder<R> {
+        Decoder {
+            reader: BufReader::new(reader),
+            format,
+            stack: Vec::new(),
+            input_pos: 0,
+            pending: None,
+        }
+    }
+
+    fn next_input_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = match self.reader.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        self.reader.consume(1);
+        self.input_pos += 1;
+        Ok(Some(byte))
+    }
+
+    // Called just after a top-level '(' has been read.
+    fn push_input_marker(&mut self) -> io::Result<()> {
+        let marker_pos = self.input_pos - 1;
+        let mut text = Vec::new();
+        loop {
+            match self.next_input_byte()? {
+                Some(b')') => break,
+                Some(byte) if text.len() < MAX_MARKER_LEN => text.push(byte),
+                Some(_) => return Err(invalid(DecompressError::BadCount { pos: marker_pos })),
+                None => {
+                    return Err(invalid(DecompressError::UnterminatedMarker { pos: marker_pos }));
+                }
+            }
+        }
+        let (width, count) = marker::parse_marker_text(&text, marker_pos).map_err(invalid)?;
+
+        // The width comes from the input, so only take as much as is there.
+        let base = self.input_pos;
+        let mut block = Vec::new();
+        (&mut self.reader).take(width as u64).read_to_end(&mut block)?;
+        self.input_pos += block.len();
+        if block.len() < width {
+            return Err(invalid(DecompressError::BlockPastEnd {
+                pos: marker_pos,
+                width,
+                available: block.len(),
+            }));
+        }
+        if count > 0 && width > 0 {
+            self.stack.push(Frame {
+                data: Rc::new(block),
+                base,
+                start: 0,
+                end: width,
+                pos: 0,
+                repeats_left: count,
+            });
+        }
+        Ok(())
+    }
+
+    // Called with the innermost frame positioned on a '('. Only used for v2.
+    fn push_frame_marker(&mut self) -> io::Result<()> {
+        let child = {
+            let frame = self.stack.last_mut().unwrap();
+            let marker = marker::parse_marker(&frame.data[..frame.end], frame.pos, frame.base)
+                .map_err(invalid)?;
+            frame.pos = marker.end;
+            Frame {
+                data: frame.data.clone(),
+                base: frame.base,
+                start: marker.start,
+                end: marker.end,
+                pos: marker.start,
+                repeats_left: marker.count,
+            }
+        };
+        if child.repeats_left > 0 && child.start < child.end {
+            self.stack.push(child);
+        }
+        Ok(())
+    }
+
+    // Decode into buf[*n..], advancing *n past every byte written.
+    fn decode_into(&mut self, buf: &mut [u8], n: &mut usize) -> io::Result<()> {
+        while *n < buf.len() {
+            let format = self.format;
+            let mut at_marker = false;
+            match self.stack.last_mut() {
+                Some(frame) => {
+                    if frame.pos == frame.end {
+                        if frame.repeats_left > 1 {
+                            frame.repeats_left -= 1;
+                            frame.pos = frame.start;
+                        } else {
+                            self.stack.pop();
+                        }
+                        continue;
+                    }
+
+                    // Copy as long a run as possible in one go.
+                    let available = &frame.data[frame.pos..frame.end];
+                    let space = buf.len() - *n;
+                    let run = match format {
+                        Format::V1 => available.len().min(space),
+                        Format::V2 => {
+                            available.iter()
+                                .take(space)
+                                .position(|&b| b == b'(' || b <= 32)
+                                .unwrap_or_else(|| available.len().min(space))
+                        }
+                    };
+                    buf[*n..*n + run].copy_from_slice(&available[..run]);
+                    frame.pos += run;
+                    *n += run;
+
+                    if run == 0 {
+                        if available[0] == b'(' {
+                            at_marker = true;
+                        } else {
+                            // Skip non-printing ASCII characters <= 32.
+                            frame.pos += 1;
+                        }
+                    }
+                }
+                None => {
+                    match self.next_input_byte()? {
+                        None => break,
+                        Some(b'(') => self.push_input_marker()?,
+                        Some(byte) if byte <= 32 => {}
+                        Some(byte) => {
+                            buf[*n] = byte;
+                            *n += 1;
+                        }
+                    }
+                }
+            }
+            if at_marker {
+                self.push_frame_marker()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = self.pending.take() {
+            return Err(error);
+        }
+        let mut n = 0;
+        match self.decode_into(buf, &mut n) {
+            // Hand over what was decoded; the error comes with the next read.
+            Err(error) if n > 0 => {
+                self.pending = Some(error);
+                Ok(n)
+            }
+            Err(error) => Err(error),
+            Ok(()) => Ok(n),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stream(input: &str, format: Format) -> Vec<u8> {
+        let mut output = Vec::new();
+        Decoder::new(input.as_bytes(), format).read_to_end(&mut output).unwrap();
+        output
+    }
+
+    // Read one byte at a time, to exercise resuming mid-block.
+    fn stream_slowly(input: &str, format: Format) -> Vec<u8> {
+        let mut decoder = Decoder::new(input.as_bytes(), format);
+        let mut output = Vec::new();
+        let mut byte = [0u8; 1];
+        while decoder.read(&mut byte).unwrap() == 1 {
+            output.push(byte[0]);
+        }
+        output
+    }
+
+    const EXAMPLES: [&str; 8] = ["ADVENT",
+                                 "A(1x5)BC",
+                                 "(3x3)XYZ",
+                                 "A(2x2)BCD(2x2)EFG",
+                                 "(6x1)(1x3)A",
+                                 "X(8x2)(3x3)ABCY\n",
+                                 "(25x3)(3x3)ABC(2x3)XY(5x2)PQRSTX(18x9)(3x2)TWO(5x7)SEVEN",
+                                 "A(0x5)B(3x0)CDEF"];
+
+    #[test]
+    fn matches_v1() {
+        for input in EXAMPLES.iter() {
+            assert_eq!(stream(input, Format::V1), ::decompress(input.as_bytes()).unwrap());
+            assert_eq!(stream_slowly(input, Format::V1), ::decompress(input.as_bytes()).unwrap());
+        }
+    }
+
+    #[test]
+    fn matches_v2() {
+        for input in EXAMPLES.iter() {
+            assert_eq!(stream(input, Format::V2), ::decompress_v2(input.as_bytes()).unwrap());
+            assert_eq!(stream_slowly(input, Format::V2), ::decompress_v2(input.as_bytes()).unwrap());
+        }
+    }
+
+    #[test]
+    fn large_v2_expansion() {
+        let input = "(27x12)(20x12)(13x14)(7x10)(1x12)A";
+        let mut decoder = Decoder::new(input.as_bytes(), Format::V2);
+        let copied = io::copy(&mut decoder, &mut io::sink()).unwrap();
+        assert_eq!(copied, 241920);
+    }
+
+    // The DecompressError behind a decoding failure.
+    fn stream_error(input: &str, format: Format) -> DecompressError {
+        let mut sink = Vec::new();
+        let err = Decoder::new(input.as_bytes(), format).read_to_end(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.get_ref().and_then(|e| e.downcast_ref::<DecompressError>()).unwrap().clone()
+    }
+
+    #[test]
+    fn errors() {
+        // The same errors, at the same offsets, as decoding in memory.
+        assert_eq!(stream_error("AB(3x2", Format::V1),
+                   DecompressError::UnterminatedMarker { pos: 2 });
+        assert_eq!(stream_error("A(3xq)ABC", Format::V1), DecompressError::BadCount { pos: 1 });
+        assert_eq!(stream_error("(5x2)AB", Format::V1),
+                   DecompressError::BlockPastEnd { pos: 0, width: 5, available: 2 });
+        assert_eq!(stream_error("XY(7x2)(3x3)AB", Format::V2),
+                   DecompressError::BlockPastEnd { pos: 7, width: 3, available: 2 });
+        assert_eq!(stream_error("(10x2)AB(1x)CDEF", Format::V2),
+                   DecompressError::BadCount { pos: 8 });
+        assert_eq!(stream_error("(6x2)(9x2)AB", Format::V2),
+                   DecompressError::BlockPastEnd { pos: 5, width: 9, available: 1 });
+
+        // A huge width is a truncated block, not a huge allocation.
+        assert_eq!(stream_error("(99999999999999x1)A", Format::V1),
+                   DecompressError::BlockPastEnd { pos: 0, width: 99999999999999, available: 1 });
+        assert_eq!(stream_error("A(5x2)(1x0", Format::V1),
+                   DecompressError::BlockPastEnd { pos: 1, width: 5, available: 4 });
+    }
+
+    #[test]
+    fn output_before_error() {
+        // Bytes decoded before a bad marker are still delivered.
+        let mut decoder = Decoder::new("HELLO(3x2".as_bytes(), Format::V1);
+        let mut output = Vec::new();
+        let err = io::copy(&mut decoder, &mut output).unwrap_err();
+        assert_eq!(output, b"HELLO");
+        assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<DecompressError>()),
+                   Some(&DecompressError::UnterminatedMarker { pos: 5 }));
+
+        let mut decoder = Decoder::new("AB(2x2)CD(1xq)E".as_bytes(), Format::V2);
+        let mut buf = [0u8; 16];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"ABCDCD");
+        assert!(decoder.read(&mut buf).is_err());
+    }
+}